@@ -0,0 +1,339 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Index, LitInt, LitStr, parse_macro_input};
+
+/// Implements `kvantuma::ecs::component::Component`. Types which need to be
+/// dropped are stored as `Extern` components, the rest as `Pod`.
+///
+/// `#[component(stable)]` gives the component a stable id hashed from its
+/// module path and name, which only changes when the type is moved or
+/// renamed. `#[component(id = "...")]` hashes the given string instead, and
+/// is required for generic types.
+/// `#[component(sparse)]` stores it in a sparse set instead of archetype
+/// tables, for components which are added and removed often.
+#[proc_macro_derive(Component, attributes(component))]
+pub fn derive_component(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match component(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn component(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut stable_id = None;
+    let mut sparse = false;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("component")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("stable") {
+                if !input.generics.params.is_empty() {
+                    return Err(meta.error("generic components need an explicit `id = \"...\"`"));
+                }
+                // Unlike `type_name`, the result does not depend on the compiler
+                stable_id = Some(quote!(::std::concat!(::std::module_path!(), "::", ::std::stringify!(#name))));
+                Ok(())
+            } else if meta.path.is_ident("sparse") {
+                sparse = true;
+                Ok(())
+            } else if meta.path.is_ident("id") {
+                let id = meta.value()?.parse::<LitStr>()?;
+                stable_id = Some(quote!(#id));
+                Ok(())
+            } else {
+                Err(meta.error("expected `stable`, `sparse` or `id = \"...\"`"))
+            }
+        })?;
+    }
+
+    let stable_id = stable_id.map(|name| quote! {
+        fn stable_id() -> ::std::option::Option<::kvantuma::ecs::component::StableId> {
+            ::std::option::Option::Some(::kvantuma::ecs::component::StableId::from_name(#name))
+        }
+
+        fn stable(&self) -> ::std::option::Option<::kvantuma::ecs::component::StableId> {
+            <Self as ::kvantuma::ecs::component::Component>::stable_id()
+        }
+    });
+
+    let storage = sparse.then(|| quote! {
+        fn storage_type() -> ::kvantuma::ecs::sparse::StorageType {
+            ::kvantuma::ecs::sparse::StorageType::Sparse
+        }
+
+        fn storage(&self) -> ::kvantuma::ecs::sparse::StorageType {
+            ::kvantuma::ecs::sparse::StorageType::Sparse
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::kvantuma::ecs::component::Component for #name #ty_generics #where_clause {
+            fn component_id() -> ::kvantuma::ecs::component::ComponentId {
+                ::kvantuma::ecs::component::component_id::<Self>()
+            }
+
+            #stable_id
+
+            #storage
+
+            fn id(&self) -> ::kvantuma::ecs::component::ComponentId {
+                ::kvantuma::ecs::component::component_id::<Self>()
+            }
+
+            fn layout(&self) -> ::std::alloc::Layout {
+                ::std::alloc::Layout::new::<Self>()
+            }
+
+            fn kind(&self) -> ::kvantuma::ecs::component::ComponentKind {
+                if ::std::mem::needs_drop::<Self>() {
+                    ::kvantuma::ecs::component::ComponentKind::Extern
+                } else {
+                    ::kvantuma::ecs::component::ComponentKind::Pod
+                }
+            }
+
+            fn drop_fn(&self) -> ::std::option::Option<unsafe fn(*mut u8)> {
+                if ::std::mem::needs_drop::<Self>() {
+                    ::std::option::Option::Some(|ptr| unsafe { ::std::ptr::drop_in_place(ptr as *mut Self) })
+                } else {
+                    ::std::option::Option::None
+                }
+            }
+        }
+    })
+}
+
+/// Implements `kvantuma::ecs::world::ComponentsBundle` for a struct whose
+/// named fields are components
+#[proc_macro_derive(Bundle)]
+pub fn derive_bundle(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match named_fields(&input, "Bundle") {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let field_names = fields.iter().map(|field| &field.ident);
+
+    quote! {
+        // SAFETY: every field is a component and passed once
+        unsafe impl #impl_generics ::kvantuma::ecs::world::ComponentsBundle for #name #ty_generics #where_clause {
+            fn for_each(&self, f: &mut dyn FnMut(&dyn ::kvantuma::ecs::component::Component)) {
+                #(f(&self.#field_names);)*
+            }
+        }
+    }
+    .into()
+}
+
+/// Implements `kvantuma::render::vertex::VertexLayout`, with one attribute
+/// per field in declaration order. Field types must implement
+/// `VertexAttributeFormat`.
+///
+/// `#[vertex(instance)]` on the struct makes the layout step per instance,
+/// and `#[vertex(location = N)]` on a field moves it and the following
+/// fields to shader location `N`.
+#[proc_macro_derive(VertexLayout, attributes(vertex))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match vertex_layout(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn vertex_layout(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "VertexLayout cannot be derived for generic types"));
+    }
+
+    let mut instance = false;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("vertex")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("instance") {
+                instance = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `instance`"))
+            }
+        })?;
+    }
+
+    let mut next_location = 0u32;
+    let mut attributes = vec![];
+    for field in named_fields(input, "VertexLayout")? {
+        let mut location = next_location;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("vertex")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("location") {
+                    location = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `location = N`"))
+                }
+            })?;
+        }
+        next_location = location + 1;
+
+        let ident = &field.ident;
+        let ty = &field.ty;
+        attributes.push(quote! {
+            ::kvantuma::render::types::VertexAttribute {
+                format: <#ty as ::kvantuma::render::vertex::VertexAttributeFormat>::FORMAT,
+                offset: ::std::mem::offset_of!(#name, #ident) as ::kvantuma::render::types::BufferAddress,
+                shader_location: #location,
+            }
+        });
+    }
+
+    let step_mode = if instance {
+        quote!(::kvantuma::render::types::VertexStepMode::Instance)
+    } else {
+        quote!(::kvantuma::render::types::VertexStepMode::Vertex)
+    };
+
+    Ok(quote! {
+        impl ::kvantuma::render::vertex::VertexLayout for #name {
+            fn vertex_buffer_layout() -> ::kvantuma::render::types::VertexBufferLayout<'static> {
+                const ATTRIBUTES: &[::kvantuma::render::types::VertexAttribute] = &[#(#attributes),*];
+
+                ::kvantuma::render::types::VertexBufferLayout {
+                    array_stride: ::std::mem::size_of::<#name>() as ::kvantuma::render::types::BufferAddress,
+                    step_mode: #step_mode,
+                    attributes: ATTRIBUTES,
+                }
+            }
+        }
+    })
+}
+
+/// Implements `kvantuma::ecs::reflect::Reflect` for a struct. Fields are
+/// named by their identifier, or by their index in tuple structs.
+/// `#[reflect(ignore)]` hides a field, which then does not need to
+/// implement `Reflect`.
+#[proc_macro_derive(Reflect, attributes(reflect))]
+pub fn derive_reflect(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match reflect(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn reflect(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(Span::call_site(), "Reflect can only be derived for structs, use `reflect_value!` for other types"));
+    };
+
+    let mut names = vec![];
+    let mut members = vec![];
+    let mut types = vec![];
+    for (index, field) in data.fields.iter().enumerate() {
+        let mut ignore = false;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("reflect")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("ignore") {
+                    ignore = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `ignore`"))
+                }
+            })?;
+        }
+        if ignore {
+            continue;
+        }
+
+        match &field.ident {
+            Some(ident) => {
+                names.push(ident.to_string());
+                members.push(quote!(#ident));
+            }
+            None => {
+                let index = Index::from(index);
+                names.push(index.index.to_string());
+                members.push(quote!(#index));
+            }
+        }
+        types.push(&field.ty);
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::kvantuma::ecs::reflect::Reflect for #name #ty_generics #where_clause {
+            fn reflect_type_info() -> ::kvantuma::ecs::reflect::TypeInfo {
+                ::kvantuma::ecs::reflect::TypeInfo {
+                    type_name: ::std::any::type_name::<Self>(),
+                    fields: vec![#(
+                        ::kvantuma::ecs::reflect::FieldInfo {
+                            name: #names,
+                            type_name: ::std::any::type_name::<#types>(),
+                        }
+                    ),*],
+                }
+            }
+
+            fn type_info(&self) -> ::kvantuma::ecs::reflect::TypeInfo {
+                <Self as ::kvantuma::ecs::reflect::Reflect>::reflect_type_info()
+            }
+
+            fn field(&self, name: &str) -> ::std::option::Option<&dyn ::kvantuma::ecs::reflect::Reflect> {
+                match name {
+                    #(#names => ::std::option::Option::Some(&self.#members),)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+
+            fn field_mut(&mut self, name: &str) -> ::std::option::Option<&mut dyn ::kvantuma::ecs::reflect::Reflect> {
+                match name {
+                    #(#names => ::std::option::Option::Some(&mut self.#members),)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+
+            fn apply(&mut self, value: &dyn ::kvantuma::ecs::reflect::Reflect) -> bool {
+                if !value.as_any().is::<Self>() {
+                    return false;
+                }
+
+                #(
+                    if let ::std::option::Option::Some(field) = value.field(#names) {
+                        ::kvantuma::ecs::reflect::Reflect::apply(&mut self.#members, field);
+                    }
+                )*
+
+                true
+            }
+
+            fn as_any(&self) -> &dyn ::std::any::Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn ::std::any::Any {
+                self
+            }
+
+            fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn ::std::any::Any> {
+                self
+            }
+        }
+    })
+}
+
+fn named_fields<'a>(input: &'a DeriveInput, derive: &str) -> syn::Result<Vec<&'a syn::Field>> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields.named.iter().collect()),
+            _ => Err(Error::new_spanned(&input.ident, format!("{derive} can only be derived for structs with named fields"))),
+        },
+        _ => Err(Error::new(Span::call_site(), format!("{derive} can only be derived for structs"))),
+    }
+}
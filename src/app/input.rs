@@ -0,0 +1,47 @@
+use std::collections::HashSet;
+
+use glam::DVec2;
+use glfw::{Action, Key, MouseButton, WindowEvent};
+
+/// Keyboard and mouse state, kept up to date by the app as a world resource
+#[derive(Debug, Default)]
+pub struct Input {
+    keys: HashSet<Key>,
+    mouse_buttons: HashSet<MouseButton>,
+    cursor_position: DVec2,
+}
+
+impl Input {
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::Key(key, _, Action::Press, _) => {
+                self.keys.insert(key);
+            }
+            WindowEvent::Key(key, _, Action::Release, _) => {
+                self.keys.remove(&key);
+            }
+            WindowEvent::MouseButton(button, Action::Press, _) => {
+                self.mouse_buttons.insert(button);
+            }
+            WindowEvent::MouseButton(button, Action::Release, _) => {
+                self.mouse_buttons.remove(&button);
+            }
+            WindowEvent::CursorPos(x, y) => {
+                self.cursor_position = DVec2::new(x, y);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_key_pressed(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.contains(&button)
+    }
+
+    pub fn cursor_position(&self) -> DVec2 {
+        self.cursor_position
+    }
+}
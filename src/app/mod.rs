@@ -1,10 +1,11 @@
 use glam::UVec2;
 use glfw::{Glfw, PWindow, WindowEvent};
 
-use crate::{app::{helper::{GameLoopCallbacks, game_loop}, window::{Events, WindowDescriptor, WindowMode}}, ecs::world::World, error::GameError, render::{RenderDevice, error::RenderError}};
+use crate::{app::{helper::{GameLoopCallbacks, game_loop}, input::Input, time::GameTime, window::{Events, WindowDescriptor, WindowMode}}, ecs::{hierarchy::{Children, Parent}, reflect::TypeRegistry, transform::{GlobalTransform, Transform}, world::World}, error::GameError, physics::PhysicsWorld, render::{RenderDevice, error::RenderError, registry::RenderRegistry}};
 
 pub mod base;
 pub mod helper;
+pub mod input;
 pub mod time;
 pub mod window;
 
@@ -53,7 +54,26 @@ impl<G> App<G> {
         let mut glfw = glfw::init(glfw::fail_on_errors)?;
         glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::NoApi));
 
-        let world = World::new();
+        let mut world = World::new();
+        world.insert_resource(GameTime::default());
+        world.insert_resource(Input::default());
+        world.insert_resource(RenderRegistry::new());
+        world.insert_resource(PhysicsWorld::new());
+        world.add_event::<WindowEvent>();
+
+        let mut registry = TypeRegistry::new();
+        registry.register::<Transform>().with_default().with_serde().with_clone().with_component();
+        registry.register::<GlobalTransform>().with_default().with_serde().with_clone().with_component();
+        registry.register::<Parent>().with_serde().with_map_entities().with_component();
+        registry.register::<Children>().with_default().with_serde().with_map_entities().with_component();
+        world.insert_resource(registry);
+
+        match kira::AudioManager::<kira::DefaultBackend>::new(kira::AudioManagerSettings::default()) {
+            Ok(audio) => {
+                world.insert_non_send_resource(audio);
+            }
+            Err(e) => log::warn!("Cannot initialize audio, continuing without sound: {e}"),
+        }
 
         let (mut window, events) = glfw.with_primary_monitor(|glfw, m| {
             glfw.create_window(
@@ -75,6 +95,7 @@ impl<G> App<G> {
         window.set_framebuffer_size_polling(true);
         window.set_key_polling(true);
         window.set_mouse_button_polling(true);
+        window.set_cursor_pos_polling(true);
         window.set_pos_polling(true);
 
         Ok(App { 
@@ -106,10 +127,17 @@ impl<G: Game + 'static> App<G> {
             game_state, 
             240, 0.1, 
             GameLoopCallbacks {
-                update: |g| g.game.game.update(&mut g.game.world)
-                    .unwrap_or_else(|e| panic!("Failed game update: {e}")),
+                update: |g| {
+                    let time = GameTime::from_loop(g);
+                    g.game.world.insert_resource(time);
+                    g.game.world.update_events();
+
+                    g.game.game.update(&mut g.game.world)
+                        .unwrap_or_else(|e| panic!("Failed game update: {e}"));
+                },
                 render: |g| match g.game.game.render(&mut g.game.world, &mut g.game.render_device) {
-                    Ok(_) => {}
+                    // One rendered frame is one change detection run
+                    Ok(_) => g.game.world.advance_tick(),
                     Err(RenderError::Lost) => {
                         log::error!("The underlying surface has changed, and therefore the swap chain must be updated");
                     }
@@ -130,6 +158,11 @@ impl<G: Game + 'static> App<G> {
                         _ => {}
                     }
 
+                    if let Some(input) = g.game.world.resource_mut::<Input>() {
+                        input.handle_event(e);
+                    }
+                    g.game.world.send_event(e.clone());
+
                     g.game.game.input(e, &mut g.game.world)
                         .unwrap_or_else(|err| panic!("Failed game input: {err}"));
                 },
@@ -7,6 +7,36 @@ pub trait TimeTrait : Copy {
 
 pub use time_impl::*;
 
+use super::base::GameLoop;
+
+/// Timing of the game loop, kept up to date by the app as a world resource
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GameTime {
+    /// Duration of the last frame in seconds
+    pub delta: f64,
+    /// Duration of one fixed update in seconds
+    pub fixed_delta: f64,
+    /// Total running time in seconds
+    pub elapsed: f64,
+    /// Progress between the last and the next fixed update, in 0..1
+    pub blending_factor: f64,
+    pub updates: u64,
+    pub renders: u64,
+}
+
+impl GameTime {
+    pub fn from_loop<G, T: TimeTrait, W>(game_loop: &GameLoop<G, T, W>) -> GameTime {
+        GameTime {
+            delta: game_loop.last_frame_time(),
+            fixed_delta: game_loop.fixed_time_step(),
+            elapsed: game_loop.running_time(),
+            blending_factor: game_loop.blending_factor(),
+            updates: game_loop.number_of_updates(),
+            renders: game_loop.number_of_renders(),
+        }
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 mod time_impl {
     use super::*;
@@ -1,38 +1,252 @@
-use std::{alloc::{Layout, alloc}, ptr::NonNull};
+use std::{alloc::{Layout, alloc}, cell::UnsafeCell, collections::HashMap, ops::{Index, IndexMut}, ptr::NonNull};
+
+use slotmap::{Key, KeyData, new_key_type};
+use smallvec::SmallVec;
 
 use crate::ecs::component::{Component, ComponentId, ComponentKind, ErasedComponent};
 
 use super::component::ComponentMeta;
+use super::reflect::RawCloneFn;
+use super::tick::{ComponentTicks, Tick};
+
+new_key_type! {
+    pub struct EntityId;
+}
+
+/// Where the components of an alive entity are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EntityLocation {
+    pub archetype: usize,
+    pub row: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct EntitySlot {
+    /// Odd while the slot holds an alive entity
+    version: u32,
+    /// Highest version handed out for the slot
+    issued: u32,
+    location: EntityLocation,
+}
+
+impl EntitySlot {
+    fn is_alive(&self) -> bool {
+        self.version % 2 == 1
+    }
+
+    /// Slots which handed out every version are never reused
+    fn is_exhausted(&self) -> bool {
+        self.issued >= u32::MAX - 2
+    }
+
+    fn free(&mut self) {
+        self.version = self.issued.wrapping_add(1);
+    }
+}
+
+/// Locations of alive entities, keyed by generational ids. Despawned ids
+/// never become alive again: their slots are reused with newer versions,
+/// also after [`Entities::restore`].
+#[derive(Debug, Clone, Default)]
+pub struct Entities {
+    slots: Vec<EntitySlot>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl Entities {
+    pub fn insert(&mut self, location: EntityLocation) -> EntityId {
+        let index = self.free.pop().unwrap_or_else(|| {
+            self.slots.push(EntitySlot::default());
+            (self.slots.len() - 1) as u32
+        });
+
+        let slot = &mut self.slots[index as usize];
+        slot.issued = (slot.issued + 1) | 1;
+        slot.version = slot.issued;
+        slot.location = location;
+        self.len += 1;
+
+        key(index, slot.version)
+    }
+
+    pub fn remove(&mut self, entity: EntityId) -> Option<EntityLocation> {
+        let (index, version) = split(entity);
+        let slot = self.slots
+            .get_mut(index as usize)
+            .filter(|slot| slot.version == version && slot.is_alive())?;
+
+        slot.free();
+        if !slot.is_exhausted() {
+            self.free.push(index);
+        }
+        self.len -= 1;
+
+        Some(slot.location)
+    }
+
+    pub fn get(&self, entity: EntityId) -> Option<&EntityLocation> {
+        let (index, version) = split(entity);
+        self.slots
+            .get(index as usize)
+            .filter(|slot| slot.version == version && slot.is_alive())
+            .map(|slot| &slot.location)
+    }
+
+    pub fn get_mut(&mut self, entity: EntityId) -> Option<&mut EntityLocation> {
+        let (index, version) = split(entity);
+        self.slots
+            .get_mut(index as usize)
+            .filter(|slot| slot.version == version && slot.is_alive())
+            .map(|slot| &mut slot.location)
+    }
+
+    pub fn contains_key(&self, entity: EntityId) -> bool {
+        self.get(entity).is_some()
+    }
 
-const MAX_COMPONENTS: usize = 256;
-const WORDS: usize = MAX_COMPONENTS / u64::BITS as usize;
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_alive())
+            .map(|(index, slot)| key(index as u32, slot.version))
+    }
+
+    /// Removes every entity
+    pub fn clear(&mut self) {
+        self.restore(&Entities::default());
+    }
+
+    /// Makes the entities of `saved` alive again at their saved locations
+    /// and removes the others. Versions handed out since `saved` was taken
+    /// stay used, so ids of removed entities are not handed out again.
+    pub fn restore(&mut self, saved: &Entities) {
+        if self.slots.len() < saved.slots.len() {
+            self.slots.resize(saved.slots.len(), EntitySlot::default());
+        }
+
+        self.free.clear();
+        self.len = 0;
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            match saved.slots.get(index).filter(|saved| saved.is_alive()) {
+                Some(saved) => {
+                    slot.version = saved.version;
+                    slot.issued = slot.issued.max(saved.issued);
+                    slot.location = saved.location;
+                    self.len += 1;
+                }
+                None => {
+                    slot.free();
+                    if !slot.is_exhausted() {
+                        self.free.push(index as u32);
+                    }
+                }
+            }
+        }
+
+        // Reuse low slots first, like a fresh allocator
+        self.free.reverse();
+    }
+}
 
-pub type EntityId = u32;
+impl Index<EntityId> for Entities {
+    type Output = EntityLocation;
 
-#[derive(Clone)]
+    fn index(&self, entity: EntityId) -> &EntityLocation {
+        self.get(entity).expect("Entity is alive")
+    }
+}
+
+impl IndexMut<EntityId> for Entities {
+    fn index_mut(&mut self, entity: EntityId) -> &mut EntityLocation {
+        self.get_mut(entity).expect("Entity is alive")
+    }
+}
+
+/// Ids are slotmap keys, with the slot index in the low half and the
+/// version in the high half
+fn key(index: u32, version: u32) -> EntityId {
+    KeyData::from_ffi(((version as u64) << 32) | index as u64).into()
+}
+
+fn split(entity: EntityId) -> (u32, u32) {
+    let ffi = entity.data().as_ffi();
+    (ffi as u32, (ffi >> 32) as u32)
+}
+
+/// Set of component ids. Stored inline for the first 256 ids and growing
+/// on the heap beyond them. The last word is never zero, so equal sets are
+/// equal masks.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
 pub struct ArchetypeMask {
-    words: [u64; WORDS],
+    words: SmallVec<[u64; 4]>,
 }
 
 impl ArchetypeMask {
     pub fn from_ids(ids: &[ComponentId]) -> Self {
-        if ids.len() > MAX_COMPONENTS {
-            panic!("Too many components in archetype {}", ids.len());
-        }
-
-        let mut mask = ArchetypeMask { words: [0; WORDS] };
+        let mut mask = ArchetypeMask::default();
         for &id in ids {
-            let word_index = (id as usize) / u64::BITS as usize;
-            let bit_index = (id as usize) % u64::BITS as usize;
-            mask.words[word_index] |= 1 << bit_index;
+            mask.insert(id);
         }
 
         mask
     }
 
+    pub fn insert(&mut self, id: ComponentId) {
+        let word_index = (id as usize) / u64::BITS as usize;
+        let bit_index = (id as usize) % u64::BITS as usize;
+        if word_index >= self.words.len() {
+            self.words.resize(word_index + 1, 0);
+        }
+        self.words[word_index] |= 1 << bit_index;
+    }
+
+    pub fn has(&self, id: ComponentId) -> bool {
+        let word_index = (id as usize) / u64::BITS as usize;
+        let bit_index = (id as usize) % u64::BITS as usize;
+
+        self.words
+            .get(word_index)
+            .is_some_and(|word| word & (1 << bit_index) != 0)
+    }
+
+    pub fn remove(&mut self, id: ComponentId) {
+        let word_index = (id as usize) / u64::BITS as usize;
+        let bit_index = (id as usize) % u64::BITS as usize;
+        if let Some(word) = self.words.get_mut(word_index) {
+            *word &= !(1 << bit_index);
+        }
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Whether the sets have any id in common
+    pub fn intersects(&self, other: &ArchetypeMask) -> bool {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .any(|(a, b)| a & b != 0)
+    }
+
     pub fn contains(&self, other: &ArchetypeMask) -> bool {
-        self.words.iter().zip(other.words.iter())
-            .all(|(a, b)| (a & b) == *b)
+        other.words
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| self.words.get(i).copied().unwrap_or(0) & b == b)
     }
 }
 
@@ -41,8 +255,14 @@ pub struct Column {
     pub len: usize,
     pub capacity: usize,
     pub meta: ComponentMeta,
+    pub ticks: Vec<UnsafeCell<ComponentTicks>>,
 }
 
+// SAFETY: columns only store components, which are `Send + Sync`, and the
+// world hands out access to them according to the borrow rules
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
 impl Column {
     pub fn new(
         capacity: usize, 
@@ -51,8 +271,14 @@ impl Column {
         kind: ComponentKind,
         drop_fn: Option<unsafe fn(*mut u8)>,
     ) -> Column {
-        let total_size = layout.size() * capacity;
-        let ptr = unsafe { alloc(Layout::from_size_align(total_size, layout.align()).unwrap()) };
+        // Zero-sized components are never allocated, their column only
+        // counts them
+        let (ptr, capacity) = if layout.size() == 0 {
+            (NonNull::new(std::ptr::without_provenance_mut(layout.align())).unwrap(), usize::MAX)
+        } else {
+            let ptr = unsafe { alloc(array_layout(layout, capacity)) };
+            (NonNull::new(ptr).unwrap(), capacity)
+        };
         let meta = ComponentMeta {
             id,
             kind,
@@ -61,73 +287,183 @@ impl Column {
         };
 
         Column {
-            ptr: NonNull::new(ptr).unwrap(),
+            ptr,
             len: 0,
             capacity,
             meta,
+            ticks: Vec::with_capacity(capacity.min(64)),
         }
     }
 
-    pub fn push(&mut self, component: &dyn Component) {
+    pub fn push(&mut self, component: &dyn Component, tick: Tick) {
+        unsafe { self.push_raw(component as *const _ as *const u8, ComponentTicks::new(tick)) };
+    }
+
+    pub fn push_erased(&mut self, component: &ErasedComponent, tick: Tick) {
+        unsafe { self.push_raw(component.data, ComponentTicks::new(tick)) };
+    }
+
+    /// Copies one component from `data` to the end of the column.
+    ///
+    /// # Safety
+    /// `data` must point to a valid value of the column component type, which
+    /// must not be dropped by the caller afterwards.
+    pub unsafe fn push_raw(&mut self, data: *const u8, ticks: ComponentTicks) {
         if self.len >= self.capacity {
-            let new_capacity = self.capacity * 2;
-            let new_size = self.meta.layout.size() * new_capacity;
-            let new_ptr = unsafe { alloc(Layout::from_size_align(new_size, self.meta.layout.align()).unwrap()) };
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    self.ptr.as_ptr(),
-                    new_ptr,
-                    self.meta.layout.size() * self.len,
-                );
-                std::alloc::dealloc(
-                    self.ptr.as_ptr(),
-                    Layout::from_size_align(self.meta.layout.size() * self.capacity, self.meta.layout.align()).unwrap()
-                );
-            }
-            self.ptr = NonNull::new(new_ptr).unwrap();
-            self.capacity = new_capacity;
+            self.grow(self.capacity * 2);
         }
 
         let offset = self.len * self.meta.layout.size();
         unsafe {
             std::ptr::copy_nonoverlapping(
-                component as *const _ as *const u8,
+                data,
                 self.ptr.as_ptr().add(offset),
                 self.meta.layout.size(),
             );
         }
+        self.ticks.push(UnsafeCell::new(ticks));
         self.len += 1;
     }
 
-    pub fn push_erased(&mut self, component: &ErasedComponent) {
-        if self.len >= self.capacity {
-            let new_capacity = self.capacity * 2;
-            let new_size = self.meta.layout.size() * new_capacity;
-            let new_ptr = unsafe { alloc(Layout::from_size_align(new_size, self.meta.layout.align()).unwrap()) };
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    self.ptr.as_ptr(),
-                    new_ptr,
-                    self.meta.layout.size() * self.len,
-                );
-                std::alloc::dealloc(
-                    self.ptr.as_ptr(),
-                    Layout::from_size_align(self.meta.layout.size() * self.capacity, self.meta.layout.align()).unwrap()
-                );
+    /// Copies `count` components, each `stride` bytes after the previous
+    /// one, starting at `data`, to the end of the column.
+    ///
+    /// # Safety
+    /// Same as [`Column::push_raw`], for each of the components.
+    pub unsafe fn extend_strided(&mut self, data: *const u8, stride: usize, count: usize, tick: Tick) {
+        self.reserve(count);
+
+        let size = self.meta.layout.size();
+        unsafe {
+            let dst = self.ptr.as_ptr().add(self.len * size);
+            if stride == size {
+                std::ptr::copy_nonoverlapping(data, dst, size * count);
+            } else {
+                for i in 0..count {
+                    std::ptr::copy_nonoverlapping(data.add(i * stride), dst.add(i * size), size);
+                }
             }
-            self.ptr = NonNull::new(new_ptr).unwrap();
-            self.capacity = new_capacity;
         }
+        self.ticks.extend((0..count).map(|_| UnsafeCell::new(ComponentTicks::new(tick))));
+        self.len += count;
+    }
 
-        let offset = self.len * self.meta.layout.size();
+    /// Drops the component at `row` and overwrites it with the one at `data`.
+    ///
+    /// # Safety
+    /// Same as [`Column::push_raw`].
+    pub unsafe fn replace_raw(&mut self, row: usize, data: *const u8, tick: Tick) {
+        let dst = self.get_ptr(row);
+        unsafe {
+            if let Some(drop_fn) = self.meta.drop_fn {
+                drop_fn(dst);
+            }
+            std::ptr::copy_nonoverlapping(data, dst, self.meta.layout.size());
+        }
+        self.ticks[row].get_mut().changed = tick;
+    }
+
+    /// Makes room for at least `additional` more components
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.len + additional;
+        if required > self.capacity {
+            self.grow(required.next_power_of_two());
+        }
+        self.ticks.reserve(additional);
+    }
+
+    fn grow(&mut self, new_capacity: usize) {
+        let new_ptr = unsafe { alloc(array_layout(self.meta.layout, new_capacity)) };
         unsafe {
             std::ptr::copy_nonoverlapping(
-                component.data,
-                self.ptr.as_ptr().add(offset),
-                self.meta.layout.size(),
+                self.ptr.as_ptr(),
+                new_ptr,
+                self.meta.layout.size() * self.len,
             );
+            std::alloc::dealloc(self.ptr.as_ptr(), array_layout(self.meta.layout, self.capacity));
         }
-        self.len += 1;
+        self.ptr = NonNull::new(new_ptr).unwrap();
+        self.capacity = new_capacity;
+    }
+
+    /// Copy of the column, cloning its components with `clone`, or bytewise
+    /// if there is none.
+    ///
+    /// # Safety
+    /// `clone` must clone values of the column component type, and may only
+    /// be `None` for components which can be copied bytewise.
+    pub unsafe fn clone_with(&self, clone: Option<RawCloneFn>) -> Column {
+        let mut column = Column::new(self.len.max(1), self.meta.id, self.meta.layout, self.meta.kind, self.meta.drop_fn);
+        let size = self.meta.layout.size();
+
+        unsafe {
+            match clone {
+                Some(clone) => {
+                    for row in 0..self.len {
+                        clone(self.ptr.as_ptr().add(row * size), column.ptr.as_ptr().add(row * size));
+                    }
+                }
+                None => std::ptr::copy_nonoverlapping(self.ptr.as_ptr(), column.ptr.as_ptr(), size * self.len),
+            }
+        }
+
+        column.ticks = self.ticks
+            .iter()
+            .map(|ticks| UnsafeCell::new(unsafe { *ticks.get() }))
+            .collect();
+        column.len = self.len;
+        column
+    }
+
+    /// Drops every component, keeping the allocation
+    pub fn clear(&mut self) {
+        if let Some(drop_fn) = self.meta.drop_fn {
+            for row in 0..self.len {
+                unsafe { drop_fn(self.get_ptr(row)) };
+            }
+        }
+        self.ticks.clear();
+        self.len = 0;
+    }
+
+    pub fn get_ptr(&self, row: usize) -> *mut u8 {
+        assert!(row < self.len, "Column row {row} out of bounds ({})", self.len);
+
+        unsafe { self.ptr.as_ptr().add(row * self.meta.layout.size()) }
+    }
+
+    pub fn get_ticks(&self, row: usize) -> ComponentTicks {
+        unsafe { *self.ticks[row].get() }
+    }
+
+    /// Base pointer of the change ticks, writable through shared references
+    pub fn ticks_ptr(&self) -> *mut ComponentTicks {
+        UnsafeCell::raw_get(self.ticks.as_ptr())
+    }
+
+    /// Drops the component at `row` and moves the last one into its place.
+    pub fn swap_remove(&mut self, row: usize) {
+        if let Some(drop_fn) = self.meta.drop_fn {
+            unsafe { drop_fn(self.get_ptr(row)) };
+        }
+        self.swap_remove_forget(row);
+    }
+
+    /// Same as [`Column::swap_remove`], but the removed component is not
+    /// dropped, so its ownership must have been taken before.
+    pub fn swap_remove_forget(&mut self, row: usize) {
+        assert!(row < self.len, "Column row {row} out of bounds ({})", self.len);
+
+        let size = self.meta.layout.size();
+        let last = self.len - 1;
+        unsafe {
+            let dst = self.ptr.as_ptr().add(row * size);
+            if row != last {
+                std::ptr::copy_nonoverlapping(self.ptr.as_ptr().add(last * size), dst, size);
+            }
+        }
+        self.ticks.swap_remove(row);
+        self.len -= 1;
     }
 }
 
@@ -140,16 +476,28 @@ impl Drop for Column {
                 }
             }
         }
-        unsafe {
-            std::alloc::dealloc(self.ptr.as_ptr(), Layout::from_size_align(self.meta.layout.size() * self.capacity, self.meta.layout.align()).unwrap());
+        if self.meta.layout.size() != 0 {
+            unsafe { std::alloc::dealloc(self.ptr.as_ptr(), array_layout(self.meta.layout, self.capacity)) };
         }
     }
 }
 
+fn array_layout(layout: Layout, capacity: usize) -> Layout {
+    Layout::from_size_align(layout.size() * capacity, layout.align()).unwrap()
+}
+
+/// Cached transitions to the archetypes which have one component more or less
+#[derive(Default)]
+pub struct ArchetypeEdges {
+    pub add: HashMap<ComponentId, usize>,
+    pub remove: HashMap<ComponentId, usize>,
+}
+
 pub struct Archetype {
     pub mask: ArchetypeMask,
     pub columns: Vec<Column>,
     pub entities: Vec<EntityId>,
+    pub edges: ArchetypeEdges,
 }
 
 impl Archetype {
@@ -161,15 +509,20 @@ impl Archetype {
             mask,
             columns,
             entities: vec![],
+            edges: ArchetypeEdges::default(),
         }
     }
 
+    pub fn component_ids(&self) -> impl Iterator<Item = ComponentId> + '_ {
+        self.columns.iter().map(|col| col.meta.id)
+    }
+
     pub fn has_components(&self, ids: &[ComponentId]) -> bool {
         let mask = ArchetypeMask::from_ids(ids);
         self.mask.contains(&mask)
     }
 
-    pub fn get_column_with_component(&mut self, id: ComponentId) -> Option<&Column> {
+    pub fn get_column_with_component(&self, id: ComponentId) -> Option<&Column> {
         self.columns
             .iter()
             .find(|col| col.meta.id == id)
@@ -184,4 +537,182 @@ impl Archetype {
     pub fn add_entity(&mut self, id: EntityId) {
         self.entities.push(id);
     }
-}
\ No newline at end of file
+
+    /// Checks that every column holds exactly one component per entity
+    pub fn debug_assert_columns(&self) {
+        for col in &self.columns {
+            debug_assert_eq!(
+                col.len,
+                self.entities.len(),
+                "Column of component {} is out of sync with archetype entities",
+                col.meta.id,
+            );
+        }
+    }
+
+    /// Drops the components of every entity
+    pub fn clear(&mut self) {
+        for col in &mut self.columns {
+            col.clear();
+        }
+        self.entities.clear();
+    }
+
+    /// Drops every component of the entity at `row`, swapping the last entity
+    /// into its place. Returns the entity that was moved, if any.
+    pub fn swap_remove(&mut self, row: usize) -> Option<EntityId> {
+        for col in &mut self.columns {
+            col.swap_remove(row);
+        }
+        self.entities.swap_remove(row);
+        self.entities.get(row).copied()
+    }
+
+    /// Removes the entity at `row` without dropping its components, whose
+    /// ownership must have been taken before. Returns the entity that was
+    /// moved into its place, if any.
+    pub fn swap_remove_forget(&mut self, row: usize) -> Option<EntityId> {
+        for col in &mut self.columns {
+            col.swap_remove_forget(row);
+        }
+        self.entities.swap_remove(row);
+        self.entities.get(row).copied()
+    }
+
+    /// Moves the entity at `row` into `dst`, returning its new row and the
+    /// entity which took its old place, if any. Components which have no
+    /// column in `dst` are forgotten, so the caller must take them out first.
+    pub fn move_row(&mut self, row: usize, dst: &mut Archetype) -> (usize, Option<EntityId>) {
+        let new_row = dst.entities.len();
+
+        for col in &mut self.columns {
+            if let Some(dst_col) = dst.get_column_with_component_mut(col.meta.id) {
+                unsafe { dst_col.push_raw(col.get_ptr(row), col.get_ticks(row)) };
+            }
+            col.swap_remove_forget(row);
+        }
+
+        dst.add_entity(self.entities.swap_remove(row));
+
+        (new_row, self.entities.get(row).copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+    use slotmap::SlotMap;
+
+    use super::*;
+
+    #[derive(Component)]
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(Component, Clone, Copy, Debug, PartialEq)]
+    struct Value(u32);
+
+    static MARKER_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Component)]
+    #[repr(align(16))]
+    struct Marker;
+
+    impl Drop for Marker {
+        fn drop(&mut self) {
+            MARKER_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn column(component: &dyn Component) -> Column {
+        Column::new(4, component.id(), component.layout(), component.kind(), component.drop_fn())
+    }
+
+    fn archetype(columns: Vec<Column>) -> Archetype {
+        let ids = columns.iter().map(|col| col.meta.id).collect::<Vec<_>>();
+        Archetype::new(ArchetypeMask::from_ids(&ids), columns)
+    }
+
+    /// Moves the value into the column, which owns it from now on
+    fn push_column<T: Component>(col: &mut Column, value: T) {
+        col.push(&value, 0);
+        std::mem::forget(value);
+    }
+
+    fn push<T: Component>(archetype: &mut Archetype, value: T) {
+        push_column(archetype.get_column_with_component_mut(T::component_id()).unwrap(), value);
+    }
+
+    fn value(archetype: &Archetype, row: usize) -> Value {
+        let col = archetype.get_column_with_component(Value::component_id()).unwrap();
+        unsafe { *(col.get_ptr(row) as *const Value) }
+    }
+
+    #[test]
+    fn drop_counts() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut keys = SlotMap::<EntityId, ()>::with_key();
+        let ids = (0..3).map(|_| keys.insert(())).collect::<Vec<_>>();
+
+        let mut a = archetype(vec![column(&Counted(Arc::default())), column(&Value(0))]);
+        for (i, &id) in ids.iter().enumerate() {
+            push(&mut a, Counted(drops.clone()));
+            push(&mut a, Value(i as u32));
+            a.add_entity(id);
+        }
+
+        assert_eq!(a.swap_remove(0), Some(ids[2]));
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(value(&a, 0), Value(2));
+
+        // `Value` has no column in `b` and is forgotten, `Counted` is moved
+        let mut b = archetype(vec![column(&Counted(Arc::default()))]);
+        assert_eq!(a.move_row(1, &mut b), (0, None));
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(a.entities, [ids[2]]);
+        assert_eq!(b.entities, [ids[1]]);
+
+        b.clear();
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        assert!(b.entities.is_empty());
+        drop(a);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn zero_sized_columns() {
+        // Not counting the template value of the column
+        let mut col = column(&Marker);
+        MARKER_DROPS.store(0, Ordering::Relaxed);
+        for _ in 0..1000 {
+            push_column(&mut col, Marker);
+        }
+        assert_eq!(col.len, 1000);
+        assert_eq!(col.get_ptr(999) as usize % 16, 0);
+
+        col.swap_remove(10);
+        assert_eq!(MARKER_DROPS.load(Ordering::Relaxed), 1);
+        col.clear();
+        assert_eq!(MARKER_DROPS.load(Ordering::Relaxed), 1000);
+
+        push_column(&mut col, Marker);
+        drop(col);
+        assert_eq!(MARKER_DROPS.load(Ordering::Relaxed), 1001);
+    }
+
+    #[test]
+    fn masks_with_large_ids() {
+        let mask = ArchetypeMask::from_ids(&[3, 1000]);
+        assert!(mask.has(3));
+        assert!(mask.has(1000));
+        assert!(!mask.has(999));
+        assert!(mask.contains(&ArchetypeMask::from_ids(&[1000])));
+        assert!(!ArchetypeMask::from_ids(&[3]).contains(&mask));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{alloc::{alloc, dealloc}, ptr::NonNull};
+
+use super::archetype::EntityId;
+use super::component::{Component, ComponentId, ErasedComponent};
+use super::world::{ComponentsBundle, World};
+
+/// Component data owned by a command until it is applied
+struct OwnedComponent {
+    /// Describes the component, its data points to the owned copy
+    component: ErasedComponent,
+    moved: bool,
+}
+
+// SAFETY: the data is a component, which is `Send`
+unsafe impl Send for OwnedComponent {}
+
+impl OwnedComponent {
+    /// # Safety
+    /// The data of `component` must point to a valid value of it, which
+    /// must not be dropped by the caller afterwards.
+    unsafe fn new(component: &ErasedComponent) -> OwnedComponent {
+        let layout = component.layout;
+
+        // Zero-sized components are never allocated, but their pointer must
+        // still be aligned for the component type
+        let ptr = if layout.size() == 0 {
+            NonNull::new(std::ptr::without_provenance_mut(layout.align())).unwrap()
+        } else {
+            NonNull::new(unsafe { alloc(layout) }).unwrap()
+        };
+        unsafe { std::ptr::copy_nonoverlapping(component.data, ptr.as_ptr(), layout.size()) };
+
+        OwnedComponent {
+            component: ErasedComponent { data: ptr.as_ptr(), ..component.clone() },
+            moved: false,
+        }
+    }
+
+    fn from_bundle(components: impl ComponentsBundle) -> Vec<OwnedComponent> {
+        let mut owned = vec![];
+        components.for_each(&mut |comp| {
+            owned.push(unsafe { OwnedComponent::new(&ErasedComponent::of(comp)) });
+        });
+        std::mem::forget(components);
+
+        owned
+    }
+
+    fn from_erased(components: &[ErasedComponent]) -> Vec<OwnedComponent> {
+        components
+            .iter()
+            .map(|comp| unsafe { OwnedComponent::new(comp) })
+            .collect()
+    }
+
+    /// Lends the components to `f`, which returns whether it took ownership
+    /// of their data
+    fn consume(components: &mut [OwnedComponent], f: impl FnOnce(&[ErasedComponent]) -> bool) {
+        let erased = components
+            .iter()
+            .map(|comp| comp.component.clone())
+            .collect::<Vec<_>>();
+
+        if f(&erased) {
+            components.iter_mut().for_each(|comp| comp.moved = true);
+        }
+    }
+}
+
+impl Drop for OwnedComponent {
+    fn drop(&mut self) {
+        let component = &self.component;
+        let data = component.data as *mut u8;
+
+        unsafe {
+            if !self.moved && let Some(drop_fn) = component.drop_fn {
+                drop_fn(data);
+            }
+            if component.layout.size() != 0 {
+                dealloc(data, component.layout);
+            }
+        }
+    }
+}
+
+enum Command {
+    Spawn(Vec<OwnedComponent>),
+    Despawn(EntityId),
+    Insert(EntityId, Vec<OwnedComponent>),
+    Remove(EntityId, ComponentId),
+    Custom(Box<dyn FnOnce(&mut World) + Send>),
+}
+
+/// Queue of structural changes, which cannot be made while the world is
+/// borrowed, e.g. by a query or a system running in parallel. Commands are
+/// applied in the order they were recorded.
+#[derive(Default)]
+pub struct Commands {
+    queue: Vec<Command>,
+}
+
+impl Commands {
+    pub fn new() -> Commands {
+        Commands::default()
+    }
+
+    pub fn spawn(&mut self, components: impl ComponentsBundle) {
+        self.queue.push(Command::Spawn(OwnedComponent::from_bundle(components)));
+    }
+
+    /// Records a spawn of type-erased components, copying their data. The
+    /// caller must not drop the originals afterwards.
+    pub fn spawn_erased(&mut self, components: &[ErasedComponent]) {
+        self.queue.push(Command::Spawn(OwnedComponent::from_erased(components)));
+    }
+
+    pub fn despawn(&mut self, entity: EntityId) {
+        self.queue.push(Command::Despawn(entity));
+    }
+
+    pub fn insert(&mut self, entity: EntityId, components: impl ComponentsBundle) {
+        self.queue.push(Command::Insert(entity, OwnedComponent::from_bundle(components)));
+    }
+
+    /// Same as [`Commands::spawn_erased`] for inserting into an entity
+    pub fn insert_erased(&mut self, entity: EntityId, components: &[ErasedComponent]) {
+        self.queue.push(Command::Insert(entity, OwnedComponent::from_erased(components)));
+    }
+
+    /// Drops the component of the entity
+    pub fn remove<T: Component>(&mut self, entity: EntityId) {
+        self.remove_erased(entity, T::component_id());
+    }
+
+    pub fn remove_erased(&mut self, entity: EntityId, id: ComponentId) {
+        self.queue.push(Command::Remove(entity, id));
+    }
+
+    /// Records an arbitrary change, e.g. one which needs the id of a
+    /// spawned entity
+    pub fn add(&mut self, command: impl FnOnce(&mut World) + Send + 'static) {
+        self.queue.push(Command::Custom(Box::new(command)));
+    }
+
+    /// Moves the commands of `other` to the end of the queue, e.g. to merge
+    /// buffers filled by different threads
+    pub fn append(&mut self, other: &mut Commands) {
+        self.queue.append(&mut other.queue);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Applies and clears the recorded commands. Commands targeting
+    /// despawned entities are skipped.
+    pub fn apply(&mut self, world: &mut World) {
+        for command in self.queue.drain(..) {
+            match command {
+                Command::Spawn(mut components) => {
+                    OwnedComponent::consume(&mut components, |erased| {
+                        world.spawn_erased(erased);
+                        true
+                    });
+                }
+                Command::Despawn(entity) => {
+                    if !world.despawn(entity) {
+                        log::warn!("Cannot despawn entity {entity:?}: it does not exist");
+                    }
+                }
+                Command::Insert(entity, mut components) => {
+                    OwnedComponent::consume(&mut components, |erased| {
+                        match world.insert_erased(entity, erased) {
+                            Ok(()) => true,
+                            Err(e) => {
+                                log::warn!("Cannot insert components: {e}");
+                                false
+                            }
+                        }
+                    });
+                }
+                Command::Remove(entity, id) => {
+                    world.remove_erased(entity, id);
+                }
+                Command::Custom(command) => command(world),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    struct Position(i32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    struct Velocity(i32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    #[component(sparse)]
+    struct Tag(u32);
+
+    #[derive(Component)]
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn commands_apply_in_recorded_order() {
+        let mut world = World::new();
+        let entity = world.spawn((Position(1),));
+
+        let mut commands = Commands::new();
+        commands.insert(entity, (Velocity(2),));
+        commands.remove::<Position>(entity);
+        commands.add(move |world| {
+            assert_eq!(world.get::<Velocity>(entity), Some(&Velocity(2)));
+            assert_eq!(world.get::<Position>(entity), None);
+        });
+        commands.spawn((Position(3),));
+
+        let mut later = Commands::new();
+        later.despawn(entity);
+        later.insert(entity, (Position(4),));
+        commands.append(&mut later);
+        assert!(later.is_empty());
+        assert_eq!(commands.len(), 6);
+
+        // Nothing changes until the buffer is applied
+        assert_eq!(world.get::<Velocity>(entity), None);
+        assert_eq!(world.query::<&Position>(), [&Position(1)]);
+
+        commands.apply(&mut world);
+        assert!(commands.is_empty());
+        assert!(!world.is_alive(entity));
+        assert_eq!(world.query::<&Position>(), [&Position(3)]);
+    }
+
+    #[test]
+    fn sparse_components_keep_their_storage() {
+        let mut world = World::new();
+        let entity = world.spawn((Position(1),));
+
+        let mut commands = Commands::new();
+        commands.insert(entity, (Tag(1),));
+        commands.spawn((Position(2), Tag(2)));
+        commands.apply(&mut world);
+
+        assert_eq!(world.sparse_set(Tag::component_id()).map(|set| set.len()), Some(2));
+        assert_eq!(world.get::<Tag>(entity), Some(&Tag(1)));
+        let entities = world.query::<EntityId>();
+        for entity in entities {
+            let location = world.location(entity).unwrap();
+            assert_eq!(world.archetypes()[location.archetype].columns.len(), 1);
+        }
+    }
+
+    #[test]
+    fn unapplied_commands_drop_their_components() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut commands = Commands::new();
+        commands.spawn((Counted(drops.clone()),));
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+
+        drop(commands);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,6 +1,12 @@
-use std::{alloc::Layout, any::TypeId, collections::HashMap, sync::{OnceLock, atomic::{AtomicU32, Ordering}}};
+use std::{alloc::Layout, any::TypeId, borrow::Cow, collections::HashMap, fmt, sync::{OnceLock, atomic::{AtomicU32, Ordering}}};
 
 use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::error::EcsError;
+use super::sparse::StorageType;
+
+pub use kvantuma_derive::Component;
 
 static REGISTRY: OnceLock<Registry> = OnceLock::new();
 
@@ -9,42 +15,153 @@ struct Registry {
     next: AtomicU32,
 }
 
-pub fn component_id<T: 'static>() -> u32 {
-    let reg = REGISTRY.get_or_init(|| Registry {
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(|| Registry {
         map: Default::default(),
         next: AtomicU32::new(1),
-    });
+    })
+}
+
+pub fn component_id<T: 'static>() -> u32 {
+    let reg = registry();
 
     let mut map = reg.map.lock();
     *map.entry(TypeId::of::<T>())
         .or_insert_with(|| reg.next.fetch_add(1, Ordering::Relaxed))
 }
 
+/// Allocates an id which no Rust type uses, for components defined at
+/// runtime
+pub(super) fn dynamic_component_id() -> ComponentId {
+    registry().next.fetch_add(1, Ordering::Relaxed)
+}
+
 pub type ComponentId = u32;
 
+/// Component id which stays the same between runs and builds, unlike
+/// [`ComponentId`]s which are assigned in first-use order. Used to refer to
+/// components in persisted or networked data, see
+/// [`World::register_component`](super::world::World::register_component).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StableId(pub u64);
+
+impl StableId {
+    /// FNV-1a hash of the name, usually a fully qualified type name
+    pub const fn from_name(name: &str) -> StableId {
+        let bytes = name.as_bytes();
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            hash ^= bytes[i] as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            i += 1;
+        }
+
+        StableId(hash)
+    }
+}
+
+impl fmt::Display for StableId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Per-world mapping between stable ids and component ids
+#[derive(Debug, Default)]
+pub struct StableIds {
+    components: HashMap<StableId, (ComponentId, Cow<'static, str>)>,
+    stable: HashMap<ComponentId, StableId>,
+}
+
+impl StableIds {
+    /// Maps `stable` to the component, failing if it is already taken by
+    /// another component
+    pub fn insert(&mut self, stable: StableId, id: ComponentId, name: impl Into<Cow<'static, str>>) -> Result<(), EcsError> {
+        match self.components.get(&stable) {
+            Some((existing, _)) if *existing == id => Ok(()),
+            Some((_, first)) => Err(EcsError::StableIdCollision {
+                id: stable,
+                first: first.to_string(),
+                second: name.into().into_owned(),
+            }),
+            None => {
+                self.components.insert(stable, (id, name.into()));
+                self.stable.insert(id, stable);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn component_id(&self, stable: StableId) -> Option<ComponentId> {
+        self.components.get(&stable).map(|(id, _)| *id)
+    }
+
+    pub fn stable_id(&self, id: ComponentId) -> Option<StableId> {
+        self.stable.get(&id).copied()
+    }
+
+    /// Name the stable id was registered with
+    pub fn name(&self, stable: StableId) -> Option<&str> {
+        self.components.get(&stable).map(|(_, name)| name.as_ref())
+    }
+}
+
+#[derive(Clone)]
 pub struct ErasedComponent {
     pub id: ComponentId,
     pub data: *const u8,
     pub layout: Layout,
     pub kind: ComponentKind,
     pub drop_fn: Option<unsafe fn(*mut u8)>,
+    pub storage: StorageType,
+    /// Stable id and type name, registered when the component is first
+    /// used like for typed components. `None` if the stable id is
+    /// registered otherwise, e.g. for dynamic components.
+    pub stable: Option<(StableId, &'static str)>,
+}
+
+impl ErasedComponent {
+    /// Erased view of a typed component, whose data is moved into the
+    /// world on spawn or insert
+    pub fn of(component: &dyn Component) -> ErasedComponent {
+        ErasedComponent {
+            id: component.id(),
+            data: component as *const dyn Component as *const u8,
+            layout: component.layout(),
+            kind: component.kind(),
+            drop_fn: component.drop_fn(),
+            storage: component.storage(),
+            stable: component.stable().map(|stable| (stable, component.type_name())),
+        }
+    }
+
+    pub fn meta(&self) -> ComponentMeta {
+        ComponentMeta {
+            id: self.id,
+            kind: self.kind,
+            layout: self.layout,
+            drop_fn: self.drop_fn,
+        }
+    }
 }
 
 #[macro_export]
 macro_rules! component {
     {POD: $name:ident} => {
-        impl Component for $name {
-            fn component_id() -> ComponentId {
-                component_id::<$name>()
+        impl $crate::ecs::component::Component for $name {
+            fn component_id() -> $crate::ecs::component::ComponentId {
+                $crate::ecs::component::component_id::<$name>()
             }
-            fn id(&self) -> ComponentId {
-                component_id::<$name>()
+            fn id(&self) -> $crate::ecs::component::ComponentId {
+                $crate::ecs::component::component_id::<$name>()
             }
             fn layout(&self) -> std::alloc::Layout {
                 std::alloc::Layout::new::<Self>()
             }
-            fn kind(&self) -> ComponentKind {
-                ComponentKind::Pod
+            fn kind(&self) -> $crate::ecs::component::ComponentKind {
+                $crate::ecs::component::ComponentKind::Pod
             }
             fn drop_fn(&self) -> Option<unsafe fn(*mut u8)> {
                 None
@@ -73,17 +190,49 @@ macro_rules! component {
     };
 }
 
-pub trait Component {
+pub trait Component: Send + Sync + 'static {
     fn component_id() -> ComponentId where Self: Sized;
+
+    /// Opt-in id which is the same between runs, set with
+    /// `#[component(stable)]` or `#[component(id = "...")]`
+    fn stable_id() -> Option<StableId> where Self: Sized {
+        None
+    }
+
+    /// Same as [`Component::stable_id`], for values of the type. Must agree
+    /// with it.
+    fn stable(&self) -> Option<StableId> {
+        None
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
     fn id(&self) -> ComponentId;
+
+    /// Storage of the component type, set to [`StorageType::Sparse`] with
+    /// `#[component(sparse)]`. Must agree with [`Component::storage`].
+    fn storage_type() -> StorageType where Self: Sized {
+        StorageType::Table
+    }
+
+    fn storage(&self) -> StorageType {
+        StorageType::Table
+    }
+
     fn layout(&self) -> Layout;
     fn kind(&self) -> ComponentKind;
     fn drop_fn(&self) -> Option<unsafe fn(*mut u8)>;
-}
 
-pub enum Access {
-    Write,
-    Read,
+    fn meta(&self) -> ComponentMeta {
+        ComponentMeta {
+            id: self.id(),
+            kind: self.kind(),
+            layout: self.layout(),
+            drop_fn: self.drop_fn(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -92,6 +241,7 @@ pub enum ComponentKind {
     Extern,
 }
 
+#[derive(Clone, Copy)]
 pub struct ComponentMeta {
     pub id: ComponentId,
     pub kind: ComponentKind,
@@ -99,7 +249,16 @@ pub struct ComponentMeta {
     pub drop_fn: Option<unsafe fn(*mut u8)>,
 }
 
-pub struct ComponentQuery {
-    id: ComponentId,
-    access: Access,
-}
\ No newline at end of file
+impl ComponentMeta {
+    /// Metadata of a component type without a value of it at hand
+    pub fn of<T: Component>() -> ComponentMeta {
+        let needs_drop = std::mem::needs_drop::<T>();
+
+        ComponentMeta {
+            id: T::component_id(),
+            kind: if needs_drop { ComponentKind::Extern } else { ComponentKind::Pod },
+            layout: Layout::new::<T>(),
+            drop_fn: needs_drop.then_some(|ptr| unsafe { std::ptr::drop_in_place(ptr as *mut T) }),
+        }
+    }
+}
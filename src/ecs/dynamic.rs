@@ -0,0 +1,261 @@
+use std::{alloc::Layout, collections::HashMap};
+
+use bytemuck::Pod;
+
+use super::archetype::EntityId;
+use super::component::{ComponentId, ComponentKind, ErasedComponent};
+use super::error::EcsError;
+use super::query::validate_access_list;
+use super::sparse::StorageType;
+use super::world::{Access, ErasedQueryResult, READ, WRITE, World};
+
+/// Type of a field of a dynamic component, as seen by scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldKind {
+    Bool,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+    Vec2,
+    Vec3,
+    Vec4,
+    Entity,
+}
+
+impl FieldKind {
+    pub fn layout(&self) -> Layout {
+        match self {
+            FieldKind::Bool => Layout::new::<bool>(),
+            FieldKind::I32 => Layout::new::<i32>(),
+            FieldKind::U32 => Layout::new::<u32>(),
+            FieldKind::I64 => Layout::new::<i64>(),
+            FieldKind::U64 => Layout::new::<u64>(),
+            FieldKind::F32 => Layout::new::<f32>(),
+            FieldKind::F64 => Layout::new::<f64>(),
+            FieldKind::Vec2 => Layout::new::<[f32; 2]>(),
+            FieldKind::Vec3 => Layout::new::<[f32; 3]>(),
+            FieldKind::Vec4 => Layout::new::<[f32; 4]>(),
+            FieldKind::Entity => Layout::new::<EntityId>(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSchema {
+    pub name: String,
+    pub kind: FieldKind,
+    pub offset: usize,
+}
+
+impl FieldSchema {
+    /// Reads the field from the bytes of a component, `None` if `T` does
+    /// not have the size of the field
+    pub fn get<T: Pod>(&self, component: &[u8]) -> Option<T> {
+        let bytes = component.get(self.offset..self.offset + size_of::<T>())?;
+        (size_of::<T>() == self.kind.layout().size()).then(|| bytemuck::pod_read_unaligned(bytes))
+    }
+
+    /// Writes the field into the bytes of a component. Returns `false` if
+    /// `T` does not have the size of the field.
+    pub fn set<T: Pod>(&self, component: &mut [u8], value: T) -> bool {
+        if size_of::<T>() != self.kind.layout().size() {
+            return false;
+        }
+
+        let Some(bytes) = component.get_mut(self.offset..self.offset + size_of::<T>()) else {
+            return false;
+        };
+        bytes.copy_from_slice(bytemuck::bytes_of(&value));
+        true
+    }
+}
+
+/// Named fields of a dynamic component, laid out like a `#[repr(C)]` struct
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentSchema {
+    fields: Vec<FieldSchema>,
+    size: usize,
+    align: usize,
+}
+
+impl ComponentSchema {
+    pub fn new() -> ComponentSchema {
+        ComponentSchema {
+            fields: vec![],
+            size: 0,
+            align: 1,
+        }
+    }
+
+    /// Appends a field after the previous ones, aligned to its type
+    pub fn with_field(mut self, name: impl Into<String>, kind: FieldKind) -> Self {
+        let layout = kind.layout();
+        let offset = self.size.next_multiple_of(layout.align());
+
+        self.fields.push(FieldSchema {
+            name: name.into(),
+            kind,
+            offset,
+        });
+        self.size = offset + layout.size();
+        self.align = self.align.max(layout.align());
+        self
+    }
+
+    pub fn fields(&self) -> &[FieldSchema] {
+        &self.fields
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldSchema> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Checks that the layout can be stored in columns and that every field
+    /// fits into it at an offset aligned for the field type
+    pub fn validate(&self, component: &str, layout: Layout) -> Result<(), EcsError> {
+        let reason = if !layout.size().is_multiple_of(layout.align()) {
+            Some(format!("size {} is not a multiple of the alignment {}", layout.size(), layout.align()))
+        } else {
+            self.fields.iter().find_map(|field| {
+                let field_layout = field.kind.layout();
+                if field.offset + field_layout.size() > layout.size() {
+                    Some(format!("field `{}` does not fit", field.name))
+                } else if !field.offset.is_multiple_of(field_layout.align()) || field_layout.align() > layout.align() {
+                    Some(format!("field `{}` is not aligned", field.name))
+                } else {
+                    None
+                }
+            })
+        };
+
+        match reason {
+            Some(reason) => Err(EcsError::InvalidSchema {
+                component: component.to_string(),
+                reason,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Layout of a component holding exactly the fields of the schema
+    pub fn layout(&self) -> Layout {
+        Layout::from_size_align(self.size, self.align)
+            .expect("Field layouts are valid")
+            .pad_to_align()
+    }
+}
+
+/// Component type defined at runtime, e.g. by a script, see
+/// [`World::register_dynamic_component`]
+#[derive(Debug, Clone)]
+pub struct DynamicComponentInfo {
+    pub id: ComponentId,
+    pub name: String,
+    pub layout: Layout,
+    pub drop_fn: Option<unsafe fn(*mut u8)>,
+    pub schema: ComponentSchema,
+}
+
+impl DynamicComponentInfo {
+    /// Erased component reading its value from `data`, which is moved into
+    /// the world on spawn or insert
+    pub fn erased(&self, data: *const u8) -> ErasedComponent {
+        ErasedComponent {
+            id: self.id,
+            data,
+            layout: self.layout,
+            kind: if self.drop_fn.is_some() { ComponentKind::Extern } else { ComponentKind::Pod },
+            drop_fn: self.drop_fn,
+            storage: StorageType::Table,
+            // Registered with the component
+            stable: None,
+        }
+    }
+}
+
+/// Dynamic components registered in a world, looked up by id or name
+#[derive(Debug, Default)]
+pub struct DynamicComponents {
+    infos: HashMap<ComponentId, DynamicComponentInfo>,
+    names: HashMap<String, ComponentId>,
+}
+
+impl DynamicComponents {
+    pub(super) fn insert(&mut self, info: DynamicComponentInfo) {
+        self.names.insert(info.name.clone(), info.id);
+        self.infos.insert(info.id, info);
+    }
+
+    pub fn get(&self, id: ComponentId) -> Option<&DynamicComponentInfo> {
+        self.infos.get(&id)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&DynamicComponentInfo> {
+        self.infos.get(self.names.get(name)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DynamicComponentInfo> {
+        self.infos.values()
+    }
+}
+
+/// Query assembled at runtime from component ids, yielding the components
+/// of every matching entity as byte slices in the order they were added
+///
+/// ```ignore
+/// let rows = QueryBuilder::new()
+///     .read(position)
+///     .write(velocity)
+///     .without(frozen)
+///     .iter(&mut world)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QueryBuilder {
+    components: Vec<(ComponentId, Access)>,
+    with: Vec<ComponentId>,
+    without: Vec<ComponentId>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> QueryBuilder {
+        QueryBuilder::default()
+    }
+
+    pub fn read(mut self, id: ComponentId) -> Self {
+        self.components.push((id, READ));
+        self
+    }
+
+    pub fn write(mut self, id: ComponentId) -> Self {
+        self.components.push((id, WRITE));
+        self
+    }
+
+    /// Only entities having the component, without fetching it
+    pub fn with(mut self, id: ComponentId) -> Self {
+        self.with.push(id);
+        self
+    }
+
+    /// Only entities not having the component
+    pub fn without(mut self, id: ComponentId) -> Self {
+        self.without.push(id);
+        self
+    }
+
+    /// Components fetched by the query and how they are accessed
+    pub fn access(&self) -> &[(ComponentId, Access)] {
+        &self.components
+    }
+
+    /// Collects the rows of every matching entity. Fails if a component is
+    /// written and accessed again in the same query.
+    pub fn iter<'w>(&self, world: &'w mut World) -> Result<Vec<ErasedQueryResult<'w>>, EcsError> {
+        validate_access_list(&format!("{self:?}"), &self.components)?;
+
+        Ok(world.query_erased_filtered(&self.components, &self.with, &self.without))
+    }
+}
@@ -0,0 +1,79 @@
+use thiserror::Error;
+
+use super::{archetype::EntityId, component::{ComponentId, StableId}};
+
+#[derive(Debug, Error)]
+pub enum EcsError {
+    #[error("Entity {0:?} does not exist")]
+    NoSuchEntity(EntityId),
+
+    #[error("Query `{query}` accesses component {component} mutably more than once")]
+    QueryConflict {
+        query: String,
+        component: ComponentId,
+    },
+
+    #[error("Entity {child:?} cannot be attached to its descendant {parent:?}")]
+    HierarchyCycle {
+        child: EntityId,
+        parent: EntityId,
+    },
+
+    #[error("Component {0} occurs more than once in one bundle")]
+    DuplicateComponent(ComponentId),
+
+    #[error("Type `{0}` is not registered as a component")]
+    NotAComponent(String),
+
+    #[error("Stable component id {id} of `{second}` is already used by `{first}`")]
+    StableIdCollision {
+        id: StableId,
+        first: String,
+        second: String,
+    },
+
+    #[error("Dynamic component `{0}` is already registered")]
+    DynamicComponentExists(String),
+
+    #[error("Invalid layout of dynamic component `{component}`: {reason}")]
+    InvalidSchema {
+        component: String,
+        reason: String,
+    },
+
+    #[error("Type `{0}` is not registered as cloneable")]
+    NotCloneable(String),
+
+    #[error("Reflected value is not of type `{0}`")]
+    ReflectMismatch(String),
+
+    #[error("Cannot serialize `{type_name}`: {message}")]
+    Serialization {
+        type_name: String,
+        message: String,
+    },
+
+    #[error("Resource `{0}` does not exist")]
+    NoSuchResource(&'static str),
+
+    #[error("Invalid scene: {0}")]
+    InvalidScene(String),
+
+    #[error("Invalid prefab: {0}")]
+    InvalidPrefab(String),
+
+    #[error("Snapshot was taken from another world")]
+    ForeignSnapshot,
+
+    #[error("No snapshot of frame {0} is stored")]
+    NoSnapshot(u64),
+
+    #[error("System `{system}` is ordered relative to unknown label `{label}`")]
+    UnknownLabel {
+        system: String,
+        label: String,
+    },
+
+    #[error("Systems {0:?} have cyclic ordering constraints")]
+    ScheduleCycle(Vec<String>),
+}
@@ -0,0 +1,185 @@
+use std::marker::PhantomData;
+
+/// Double-buffered channel of events of one type, stored as a world
+/// resource. Events are kept for two updates, so readers running before
+/// the writer in an update still see them in the next one.
+pub struct Events<T> {
+    previous: Vec<(usize, T)>,
+    current: Vec<(usize, T)>,
+    count: usize,
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self {
+            previous: vec![],
+            current: vec![],
+            count: 0,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Events<T> {
+    pub fn send(&mut self, event: T) {
+        self.current.push((self.count, event));
+        self.count += 1;
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = T>) {
+        for event in events {
+            self.send(event);
+        }
+    }
+
+    /// Drops the events sent before the previous update and swaps buffers.
+    /// Called once per update by [`World::update_events`](super::world::World::update_events).
+    pub fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// Drops all stored events
+    pub fn clear(&mut self) {
+        self.previous.clear();
+        self.current.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Cursor which starts reading at the events sent from now on
+    pub fn reader(&self) -> EventReader<T> {
+        EventReader {
+            next: self.count,
+            _marker: PhantomData,
+        }
+    }
+
+    fn oldest(&self) -> usize {
+        self.previous
+            .first()
+            .or(self.current.first())
+            .map_or(self.count, |&(id, _)| id)
+    }
+}
+
+/// Sends events into a borrowed [`Events`] channel
+pub struct EventWriter<'w, T: Send + Sync + 'static> {
+    events: &'w mut Events<T>,
+}
+
+impl<'w, T: Send + Sync + 'static> EventWriter<'w, T> {
+    pub fn new(events: &'w mut Events<T>) -> EventWriter<'w, T> {
+        EventWriter { events }
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.events.send(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = T>) {
+        self.events.send_batch(events);
+    }
+}
+
+/// Position of one reader in an [`Events`] channel. Every reader owns its
+/// cursor, so each of them sees every event exactly once.
+pub struct EventReader<T> {
+    next: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for EventReader<T> {
+    /// Cursor which reads every event still stored in the channel
+    fn default() -> Self {
+        Self {
+            next: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> EventReader<T> {
+    pub fn new() -> EventReader<T> {
+        EventReader::default()
+    }
+
+    /// Events sent since the last read. Events dropped by two updates
+    /// without a read are missed.
+    pub fn read<'a>(&mut self, events: &'a Events<T>) -> impl Iterator<Item = &'a T> + use<'a, T> {
+        let next = self.next;
+        self.next = events.count;
+
+        events.previous
+            .iter()
+            .chain(events.current.iter())
+            .filter(move |&&(id, _)| id >= next)
+            .map(|(_, event)| event)
+    }
+
+    /// Number of events not read yet
+    pub fn len(&self, events: &Events<T>) -> usize {
+        events.count - self.next.max(events.oldest())
+    }
+
+    pub fn is_empty(&self, events: &Events<T>) -> bool {
+        self.len(events) == 0
+    }
+
+    /// Marks every stored event as read
+    pub fn clear(&mut self, events: &Events<T>) {
+        self.next = events.count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::world::World;
+
+    #[derive(Debug, PartialEq)]
+    struct Hit(u32);
+
+    #[test]
+    fn events_expire_after_two_updates() {
+        let mut world = World::new();
+        world.add_event::<Hit>();
+        let mut slow = EventReader::<Hit>::new();
+
+        world.send_event(Hit(1));
+        world.update_events();
+        assert_eq!(world.events::<Hit>().unwrap().len(), 1);
+
+        world.send_event(Hit(2));
+        world.update_events();
+        let events = world.events::<Hit>().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(slow.read(events).collect::<Vec<_>>(), [&Hit(2)]);
+
+        world.update_events();
+        assert!(world.events::<Hit>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn readers_see_every_event_once() {
+        let mut events = Events::default();
+        let mut early = EventReader::new();
+        events.send(Hit(1));
+        let mut late = events.reader();
+        events.send_batch([Hit(2), Hit(3)]);
+
+        assert_eq!(early.len(&events), 3);
+        assert_eq!(early.read(&events).collect::<Vec<_>>(), [&Hit(1), &Hit(2), &Hit(3)]);
+        assert_eq!(late.read(&events).collect::<Vec<_>>(), [&Hit(2), &Hit(3)]);
+        assert!(early.is_empty(&events));
+
+        events.update();
+        events.send(Hit(4));
+        assert_eq!(early.read(&events).collect::<Vec<_>>(), [&Hit(4)]);
+        assert_eq!(late.read(&events).collect::<Vec<_>>(), [&Hit(4)]);
+    }
+}
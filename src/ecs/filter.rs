@@ -0,0 +1,241 @@
+use std::marker::PhantomData;
+
+use super::archetype::Archetype;
+use super::component::Component;
+use super::query::{ComponentFetch, column_index, matches_component};
+use super::component::ComponentId;
+use super::sparse::StorageType;
+use super::tick::RunTicks;
+use super::world::{Access, READ, World};
+
+/// Narrows down the entities yielded by a query without fetching their data.
+/// Presence filters are resolved once per archetype against its mask, tick
+/// filters and presence of sparse components are checked per row.
+pub trait QueryFilter {
+    type State: Copy;
+    type Fetch: Copy;
+
+    /// Appends the components whose change ticks are read by the filter
+    fn access(access: &mut Vec<(ComponentId, Access)>);
+
+    fn matches(archetype: &Archetype) -> bool;
+
+    /// Resolves the state of an archetype passing [`QueryFilter::matches`]
+    fn state(archetype: &Archetype) -> Self::State;
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch;
+
+    /// # Safety
+    /// `row` must be in bounds of the fetched archetype.
+    unsafe fn filter(fetch: Self::Fetch, row: usize) -> bool;
+}
+
+/// Only entities having `T`
+pub struct With<T>(PhantomData<T>);
+
+/// Only entities not having `T`
+pub struct Without<T>(PhantomData<T>);
+
+/// Entities passing at least one of the filters in the tuple
+pub struct Or<F>(PhantomData<F>);
+
+/// Entities whose `T` was added since the last run of the query
+pub struct Added<T>(PhantomData<T>);
+
+/// Entities whose `T` was added or mutably dereferenced since the last run
+/// of the query
+pub struct Changed<T>(PhantomData<T>);
+
+impl QueryFilter for () {
+    type State = ();
+    type Fetch = ();
+
+    fn access(_access: &mut Vec<(ComponentId, Access)>) {}
+
+    fn matches(_archetype: &Archetype) -> bool {
+        true
+    }
+
+    fn state(_archetype: &Archetype) -> Self::State {}
+
+    fn fetch(_world: &World, _archetype: &Archetype, _state: Self::State, _ticks: RunTicks) -> Self::Fetch {}
+
+    unsafe fn filter(_fetch: Self::Fetch, _row: usize) -> bool {
+        true
+    }
+}
+
+impl<T: Component> QueryFilter for With<T> {
+    type State = Option<usize>;
+    type Fetch = ComponentFetch<T>;
+
+    fn access(_access: &mut Vec<(ComponentId, Access)>) {}
+
+    fn matches(archetype: &Archetype) -> bool {
+        matches_component::<T>(archetype)
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, _ticks: RunTicks) -> Self::Fetch {
+        ComponentFetch::new(world, archetype, state)
+    }
+
+    unsafe fn filter(fetch: Self::Fetch, row: usize) -> bool {
+        unsafe { fetch.get(row).is_some() }
+    }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+    type State = Option<usize>;
+    type Fetch = ComponentFetch<T>;
+
+    fn access(_access: &mut Vec<(ComponentId, Access)>) {}
+
+    fn matches(archetype: &Archetype) -> bool {
+        T::storage_type() == StorageType::Sparse || !archetype.mask.has(T::component_id())
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, _ticks: RunTicks) -> Self::Fetch {
+        ComponentFetch::new(world, archetype, state)
+    }
+
+    unsafe fn filter(fetch: Self::Fetch, row: usize) -> bool {
+        unsafe { fetch.get(row).is_none() }
+    }
+}
+
+impl<T: Component> QueryFilter for Added<T> {
+    type State = Option<usize>;
+    type Fetch = (ComponentFetch<T>, RunTicks);
+
+    fn access(access: &mut Vec<(ComponentId, Access)>) {
+        access.push((T::component_id(), READ));
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        matches_component::<T>(archetype)
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch {
+        (ComponentFetch::new(world, archetype, state), ticks)
+    }
+
+    unsafe fn filter((fetch, ticks): Self::Fetch, row: usize) -> bool {
+        unsafe {
+            fetch
+                .get(row)
+                .is_some_and(|(_, component_ticks)| (*component_ticks).is_added(ticks.last_run, ticks.this_run))
+        }
+    }
+}
+
+impl<T: Component> QueryFilter for Changed<T> {
+    type State = Option<usize>;
+    type Fetch = (ComponentFetch<T>, RunTicks);
+
+    fn access(access: &mut Vec<(ComponentId, Access)>) {
+        access.push((T::component_id(), READ));
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        matches_component::<T>(archetype)
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch {
+        (ComponentFetch::new(world, archetype, state), ticks)
+    }
+
+    unsafe fn filter((fetch, ticks): Self::Fetch, row: usize) -> bool {
+        unsafe {
+            fetch
+                .get(row)
+                .is_some_and(|(_, component_ticks)| (*component_ticks).is_changed(ticks.last_run, ticks.this_run))
+        }
+    }
+}
+
+macro_rules! impl_query_filter_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: QueryFilter),+> QueryFilter for ($($name,)+) {
+            type State = ($($name::State,)+);
+            type Fetch = ($($name::Fetch,)+);
+
+            fn access(access: &mut Vec<(ComponentId, Access)>) {
+                $($name::access(access);)+
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))&&+
+            }
+
+            fn state(archetype: &Archetype) -> Self::State {
+                ($($name::state(archetype),)+)
+            }
+
+            fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = state;
+                ($($name::fetch(world, archetype, $name, ticks),)+)
+            }
+
+            unsafe fn filter(fetch: Self::Fetch, row: usize) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = fetch;
+                unsafe { $($name::filter($name, row))&&+ }
+            }
+        }
+
+        impl<$($name: QueryFilter),+> QueryFilter for Or<($($name,)+)> {
+            type State = ($(Option<$name::State>,)+);
+            type Fetch = ($(Option<$name::Fetch>,)+);
+
+            fn access(access: &mut Vec<(ComponentId, Access)>) {
+                $($name::access(access);)+
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))||+
+            }
+
+            fn state(archetype: &Archetype) -> Self::State {
+                ($($name::matches(archetype).then(|| $name::state(archetype)),)+)
+            }
+
+            fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = state;
+                ($($name.map(|state| $name::fetch(world, archetype, state, ticks)),)+)
+            }
+
+            unsafe fn filter(fetch: Self::Fetch, row: usize) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = fetch;
+                $($name.is_some_and(|fetch| unsafe { $name::filter(fetch, row) }))||+
+            }
+        }
+    };
+}
+
+impl_query_filter_tuple! { A }
+impl_query_filter_tuple! { A, B }
+impl_query_filter_tuple! { A, B, C }
+impl_query_filter_tuple! { A, B, C, D }
+impl_query_filter_tuple! { A, B, C, D, E }
+impl_query_filter_tuple! { A, B, C, D, E, F }
+impl_query_filter_tuple! { A, B, C, D, E, F, G }
+impl_query_filter_tuple! { A, B, C, D, E, F, G, H }
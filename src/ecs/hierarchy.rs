@@ -0,0 +1,174 @@
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use super::archetype::EntityId;
+use super::component::Component;
+use super::error::EcsError;
+use super::reflect::Reflect;
+use super::scene::MapEntities;
+use super::world::World;
+
+/// Entity this one is attached to. Set with [`World::set_parent`], which
+/// keeps the [`Children`] of the parent in sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect, Serialize, Deserialize)]
+pub struct Parent(EntityId);
+
+impl Parent {
+    pub fn get(&self) -> EntityId {
+        self.0
+    }
+}
+
+/// Entities attached to this one, in attachment order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Component, Reflect, Serialize, Deserialize)]
+pub struct Children(Vec<EntityId>);
+
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &mut dyn FnMut(EntityId) -> EntityId) {
+        self.0 = map(self.0);
+    }
+}
+
+impl MapEntities for Children {
+    fn map_entities(&mut self, map: &mut dyn FnMut(EntityId) -> EntityId) {
+        for child in &mut self.0 {
+            *child = map(*child);
+        }
+    }
+}
+
+impl Deref for Children {
+    type Target = [EntityId];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Keeps relationships consistent when entities are despawned or their
+/// relationship components removed: children are detached from their
+/// parent, and children of a removed parent become roots.
+pub(super) fn register_hierarchy_hooks(world: &mut World) {
+    world.on_remove::<Parent>(|world, entity| {
+        let Some(parent) = world.get::<Parent>(entity).map(Parent::get) else {
+            return;
+        };
+
+        if let Some(mut children) = world.get_mut::<Children>(parent) {
+            children.0.retain(|&child| child != entity);
+        }
+    });
+
+    world.on_remove::<Children>(|world, entity| {
+        let Some(children) = world.get::<Children>(entity).cloned() else {
+            return;
+        };
+
+        for child in children.0 {
+            world.remove::<Parent>(child);
+        }
+    });
+}
+
+impl World {
+    /// Attaches `child` to `parent`, detaching it from its previous parent
+    pub fn set_parent(&mut self, child: EntityId, parent: EntityId) -> Result<(), EcsError> {
+        for entity in [child, parent] {
+            if !self.is_alive(entity) {
+                return Err(EcsError::NoSuchEntity(entity));
+            }
+        }
+
+        if self.ancestors(parent).any(|ancestor| ancestor == child) || child == parent {
+            return Err(EcsError::HierarchyCycle { child, parent });
+        }
+
+        self.remove_parent(child);
+        self.insert(child, (Parent(parent),))?;
+
+        match self.get_mut::<Children>(parent) {
+            Some(mut children) => children.0.push(child),
+            None => self.insert(parent, (Children(vec![child]),))?,
+        }
+
+        Ok(())
+    }
+
+    /// Detaches the entity from its parent, returning the parent
+    pub fn remove_parent(&mut self, child: EntityId) -> Option<EntityId> {
+        self.remove::<Parent>(child).map(|parent| parent.get())
+    }
+
+    pub fn parent(&self, entity: EntityId) -> Option<EntityId> {
+        self.get::<Parent>(entity).map(Parent::get)
+    }
+
+    pub fn children(&self, entity: EntityId) -> &[EntityId] {
+        self.get::<Children>(entity).map_or(&[], |children| children)
+    }
+
+    /// Parent, grandparent and so on up to the root
+    pub fn ancestors(&self, entity: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+        std::iter::successors(self.parent(entity), |&entity| self.parent(entity))
+    }
+
+    /// Despawns the entity together with all of its descendants. Returns
+    /// `false` if the entity was already despawned.
+    pub fn despawn_recursive(&mut self, entity: EntityId) -> bool {
+        for child in self.children(entity).to_vec() {
+            self.despawn_recursive(child);
+        }
+
+        self.despawn(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::transform::Transform;
+
+    fn spawn(world: &mut World) -> EntityId {
+        world.spawn((Transform::default(),))
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut world = World::new();
+        let [a, b, c] = [(); 3].map(|_| spawn(&mut world));
+        world.set_parent(b, a).unwrap();
+        world.set_parent(c, b).unwrap();
+
+        for (child, parent) in [(a, c), (a, b), (a, a)] {
+            assert!(matches!(
+                world.set_parent(child, parent),
+                Err(EcsError::HierarchyCycle { .. })
+            ));
+        }
+        assert_eq!(world.parent(a), None);
+        assert_eq!(world.ancestors(c).collect::<Vec<_>>(), [b, a]);
+    }
+
+    #[test]
+    fn despawn_detaches_children_and_parents() {
+        let mut world = World::new();
+        let [parent, first, second, grandchild] = [(); 4].map(|_| spawn(&mut world));
+        world.set_parent(first, parent).unwrap();
+        world.set_parent(second, parent).unwrap();
+        world.set_parent(grandchild, second).unwrap();
+
+        world.despawn(first);
+        assert_eq!(world.children(parent), [second]);
+
+        world.despawn(parent);
+        assert_eq!(world.parent(second), None);
+        assert_eq!(world.children(second), [grandchild]);
+
+        let root = spawn(&mut world);
+        world.set_parent(second, root).unwrap();
+        assert!(world.despawn_recursive(root));
+        assert!(!world.is_alive(second));
+        assert!(!world.is_alive(grandchild));
+    }
+}
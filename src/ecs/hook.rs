@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use super::archetype::EntityId;
+use super::world::World;
+
+/// Callback run by the world for one entity, see [`ComponentHooks`]
+pub type ComponentHook = Arc<dyn Fn(&mut World, EntityId) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    Add,
+    Replace,
+    Remove,
+}
+
+/// Callbacks registered for one component type. Every kind can have
+/// several of them, which run in registration order.
+#[derive(Default, Clone)]
+pub struct ComponentHooks {
+    on_add: Vec<ComponentHook>,
+    on_replace: Vec<ComponentHook>,
+    on_remove: Vec<ComponentHook>,
+}
+
+impl ComponentHooks {
+    /// Runs after the component is added to an entity which did not have
+    /// it, by spawning or inserting
+    pub fn on_add(&mut self, hook: impl Fn(&mut World, EntityId) + Send + Sync + 'static) -> &mut Self {
+        self.on_add.push(Arc::new(hook));
+        self
+    }
+
+    /// Runs before the component of an entity is overwritten by an insert,
+    /// while the old value can still be read
+    pub fn on_replace(&mut self, hook: impl Fn(&mut World, EntityId) + Send + Sync + 'static) -> &mut Self {
+        self.on_replace.push(Arc::new(hook));
+        self
+    }
+
+    /// Runs before the component is removed or its entity despawned, while
+    /// the value can still be read. Hooks must not despawn the entity again.
+    pub fn on_remove(&mut self, hook: impl Fn(&mut World, EntityId) + Send + Sync + 'static) -> &mut Self {
+        self.on_remove.push(Arc::new(hook));
+        self
+    }
+
+    pub fn get(&self, kind: HookKind) -> &[ComponentHook] {
+        match kind {
+            HookKind::Add => &self.on_add,
+            HookKind::Replace => &self.on_replace,
+            HookKind::Remove => &self.on_remove,
+        }
+    }
+}
@@ -1,5 +1,21 @@
 pub mod system;
+pub mod command;
+pub mod event;
+pub mod hook;
+pub mod hierarchy;
+pub mod transform;
+pub mod reflect;
+pub mod scene;
+pub mod prefab;
+pub mod dynamic;
+pub mod snapshot;
 pub mod world;
+pub mod query;
+pub mod filter;
+pub mod resource;
 pub mod component;
 pub mod defines;
-pub mod archetype;
\ No newline at end of file
+pub mod archetype;
+pub mod sparse;
+pub mod error;
+pub mod tick;
\ No newline at end of file
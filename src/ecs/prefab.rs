@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, mem::ManuallyDrop, path::Path};
+
+use ron::value::RawValue;
+use serde::Deserialize;
+
+use super::archetype::EntityId;
+use super::component::{Component, ErasedComponent};
+use super::error::EcsError;
+use super::hierarchy::{Children, Parent};
+use super::reflect::{Reflect, TypeRegistry};
+use super::world::{ComponentsBundle, World};
+
+impl World {
+    /// Spawns a copy of the entity with clones of its components registered
+    /// with `with_clone` in the [`TypeRegistry`] resource. Other components
+    /// are skipped with a warning. The copy is attached to the parent of the
+    /// entity, its children are not copied.
+    pub fn clone_entity(&mut self, entity: EntityId) -> Result<EntityId, EcsError> {
+        self.resource_scope(|world, registry: &mut TypeRegistry| {
+            let ids = world
+                .entity_components(entity)
+                .ok_or(EcsError::NoSuchEntity(entity))?
+                .filter(|&id| id != Parent::component_id() && id != Children::component_id())
+                .collect::<Vec<_>>();
+
+            let mut values = vec![];
+            for id in ids {
+                let Some(registration) = registry.get_by_component(id).filter(|r| r.clone.is_some()) else {
+                    log::warn!("Component with id {id} is not registered as cloneable, skipping it");
+                    continue;
+                };
+
+                let value = registration.get(world, entity).expect("Entity has the component");
+                values.push(registration.clone_value(value)?);
+            }
+
+            let clone = world.spawn_erased(&[]);
+            let result = registry
+                .insert_all(world, clone, values)
+                .and_then(|_| match world.parent(entity) {
+                    Some(parent) => world.set_parent(clone, parent),
+                    None => Ok(()),
+                });
+
+            if result.is_err() {
+                world.despawn(clone);
+            }
+
+            result.map(|_| clone)
+        })
+        .ok_or(EcsError::NoSuchResource(std::any::type_name::<TypeRegistry>()))?
+    }
+}
+
+/// Stored set of components which can be instantiated many times, e.g. an
+/// enemy type. Child prefabs are spawned as children of the instance.
+///
+/// Component types must be registered with `with_clone` and
+/// `with_component` in the [`TypeRegistry`] resource, and with `with_serde`
+/// to be loaded from RON:
+///
+/// ```ron
+/// (
+///     components: {
+///         "game::Health": (current: 10, max: 10),
+///     },
+///     children: [
+///         (components: { "kvantuma::ecs::transform::Transform": (...) }),
+///     ],
+/// )
+/// ```
+#[derive(Default)]
+pub struct Prefab {
+    components: Vec<Box<dyn Reflect>>,
+    children: Vec<Prefab>,
+}
+
+#[derive(Deserialize)]
+struct PrefabData {
+    #[serde(default)]
+    components: BTreeMap<String, Box<RawValue>>,
+    #[serde(default)]
+    children: Vec<PrefabData>,
+}
+
+impl Prefab {
+    pub fn new() -> Prefab {
+        Prefab::default()
+    }
+
+    /// Adds a component, replacing the one of the same type
+    pub fn with<T: Component + Reflect>(mut self, component: T) -> Self {
+        self.components.retain(|value| !value.as_any().is::<T>());
+        self.components.push(Box::new(component));
+        self
+    }
+
+    pub fn with_child(mut self, child: Prefab) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &dyn Reflect> {
+        self.components.iter().map(|value| &**value)
+    }
+
+    pub fn children(&self) -> &[Prefab] {
+        &self.children
+    }
+
+    /// Parses a prefab, resolving component types by their registered names
+    pub fn from_ron(ron: &str, registry: &TypeRegistry) -> Result<Prefab, EcsError> {
+        let data = ron::from_str::<PrefabData>(ron)
+            .map_err(|e| EcsError::InvalidPrefab(e.to_string()))?;
+
+        Prefab::from_data(data, registry)
+    }
+
+    pub fn load(path: impl AsRef<Path>, registry: &TypeRegistry) -> Result<Prefab, EcsError> {
+        let path = path.as_ref();
+        let ron = std::fs::read_to_string(path)
+            .map_err(|e| EcsError::InvalidPrefab(format!("{}: {e}", path.display())))?;
+
+        Prefab::from_ron(&ron, registry)
+    }
+
+    fn from_data(data: PrefabData, registry: &TypeRegistry) -> Result<Prefab, EcsError> {
+        let mut components = vec![];
+        for (name, value) in data.components {
+            let serde = registry
+                .get_by_name(&name)
+                .and_then(|registration| registration.serde)
+                .ok_or_else(|| EcsError::InvalidPrefab(format!("component `{name}` is not registered as serializable")))?;
+
+            components.push((serde.from_ron)(&value)?);
+        }
+
+        let children = data.children
+            .into_iter()
+            .map(|child| Prefab::from_data(child, registry))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Prefab {
+            components,
+            children,
+        })
+    }
+
+    /// Spawns an instance of the prefab and its children
+    pub fn instantiate(&self, world: &mut World) -> Result<EntityId, EcsError> {
+        self.instantiate_erased(world, &[])
+    }
+
+    /// Spawns an instance with `overrides` replacing or adding components of
+    /// its root entity
+    pub fn instantiate_with(&self, world: &mut World, overrides: impl ComponentsBundle) -> Result<EntityId, EcsError> {
+        let overrides = ManuallyDrop::new(overrides);
+        let mut erased = vec![];
+        overrides.for_each(&mut |comp| erased.push(ErasedComponent::of(comp)));
+
+        let result = self.instantiate_erased(world, &erased);
+        if result.is_err() {
+            // The overrides are only moved into the world on success
+            drop(ManuallyDrop::into_inner(overrides));
+        }
+
+        result
+    }
+
+    fn instantiate_erased(&self, world: &mut World, overrides: &[ErasedComponent]) -> Result<EntityId, EcsError> {
+        world
+            .resource_scope(|world, registry: &mut TypeRegistry| self.spawn(world, registry, None, overrides))
+            .ok_or(EcsError::NoSuchResource(std::any::type_name::<TypeRegistry>()))?
+    }
+
+    fn spawn(
+        &self,
+        world: &mut World,
+        registry: &TypeRegistry,
+        parent: Option<EntityId>,
+        overrides: &[ErasedComponent],
+    ) -> Result<EntityId, EcsError> {
+        let entity = world.spawn_erased(&[]);
+
+        let result = self.fill(world, registry, entity, parent, overrides);
+        if result.is_err() {
+            world.despawn_recursive(entity);
+        }
+
+        result.map(|_| entity)
+    }
+
+    /// Children are spawned first, so that the components of the entity
+    /// and the overrides are inserted last in one move, after which only
+    /// attaching to the parent can fail
+    fn fill(
+        &self,
+        world: &mut World,
+        registry: &TypeRegistry,
+        entity: EntityId,
+        parent: Option<EntityId>,
+        overrides: &[ErasedComponent],
+    ) -> Result<(), EcsError> {
+        for child in &self.children {
+            child.spawn(world, registry, Some(entity), &[])?;
+        }
+
+        let mut values = vec![];
+        for value in &self.components {
+            let registration = registry
+                .get(value.as_any().type_id())
+                .ok_or_else(|| EcsError::NotAComponent(value.type_info().type_name.to_string()))?;
+
+            if overrides.iter().all(|comp| Some(comp.id) != registration.component_id) {
+                values.push(registration.clone_value(&**value)?);
+            }
+        }
+        registry.insert_all_with(world, entity, values, overrides)?;
+
+        if let Some(parent) = parent {
+            world.set_parent(entity, parent)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,584 @@
+use std::marker::PhantomData;
+
+use rayon::prelude::*;
+
+use super::archetype::{Archetype, EntityId};
+use super::component::{Component, ComponentId};
+use super::error::EcsError;
+use super::filter::QueryFilter;
+use super::sparse::{SparseSet, StorageType};
+use super::tick::{ComponentTicks, Mut, RunTicks, Tick};
+use super::world::{Access, READ, WRITE, World, WorldId};
+
+/// Typed view into the components of matching entities. Implemented for
+/// `&T`, `&mut T`, `Option<&T>`, [`EntityId`] and tuples of up to 16 of them.
+/// `&mut T` yields a [`Mut`], which marks the component as changed on write.
+///
+/// Archetype components are matched once per archetype, sparse ones per row
+/// with [`Query::matches_row`].
+///
+/// # Safety
+/// [`Query::access`] must report every component the query reads or writes,
+/// otherwise aliasing checks cannot guarantee exclusive mutable access.
+pub unsafe trait Query {
+    type Item<'w>;
+
+    /// Per-archetype state which stays valid while the archetype exists,
+    /// e.g. column indices. Cached by [`QueryState`].
+    type State: Copy;
+
+    /// Column or sparse set pointers, resolved from [`Query::State`] before
+    /// iterating rows
+    type Fetch: Copy;
+
+    fn access(access: &mut Vec<(ComponentId, Access)>);
+
+    fn matches(archetype: &Archetype) -> bool;
+
+    /// Resolves the state of an archetype passing [`Query::matches`]
+    fn state(archetype: &Archetype) -> Self::State;
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch;
+
+    /// Whether the entity at `row` has the sparse components of the query
+    ///
+    /// # Safety
+    /// `row` must be in bounds of the fetched archetype.
+    unsafe fn matches_row(fetch: Self::Fetch, row: usize) -> bool;
+
+    /// # Safety
+    /// `row` must be in bounds of the fetched archetype and pass
+    /// [`Query::matches_row`], the access of the query must have been
+    /// validated and the item must not outlive the borrow of the world.
+    unsafe fn item<'w>(fetch: Self::Fetch, row: usize) -> Self::Item<'w>;
+}
+
+/// Query which only reads components, so its items can be shared between
+/// threads of [`QueryState::par_for_each`]
+///
+/// # Safety
+/// The query must not report any [`WRITE`] access.
+pub unsafe trait ReadOnlyQuery: Query {}
+
+/// Rejects queries which access one component mutably more than once,
+/// like `(&mut A, &A)`
+pub fn validate_access<Q: Query>() -> Result<Vec<(ComponentId, Access)>, EcsError> {
+    let mut access = vec![];
+    Q::access(&mut access);
+    validate_access_list(std::any::type_name::<Q>(), &access)?;
+
+    Ok(access)
+}
+
+/// Same as [`validate_access`] for queries assembled at runtime, named
+/// `query` in the error
+pub fn validate_access_list(query: &str, access: &[(ComponentId, Access)]) -> Result<(), EcsError> {
+    for (i, &(id, a)) in access.iter().enumerate() {
+        let conflict = access[i + 1..]
+            .iter()
+            .any(|&(other, b)| other == id && (a == WRITE || b == WRITE));
+
+        if conflict {
+            return Err(EcsError::QueryConflict {
+                query: query.to_string(),
+                component: id,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Cached query over a world. Remembers which archetypes match and where
+/// their columns are, picking up archetypes created since the last use.
+/// Tick-based filters report changes made since the previous iteration.
+pub struct QueryState<Q: Query, F: QueryFilter = ()> {
+    world_id: WorldId,
+    archetypes: Vec<(usize, Q::State, F::State)>,
+    archetype_count: usize,
+    last_run: Tick,
+    batch_size: usize,
+    _marker: PhantomData<fn() -> (Q, F)>,
+}
+
+impl<Q: Query, F: QueryFilter> QueryState<Q, F> {
+    pub fn new(world: &World) -> Result<QueryState<Q, F>, EcsError> {
+        validate_access::<Q>()?;
+
+        let mut state = QueryState {
+            world_id: world.id(),
+            archetypes: vec![],
+            archetype_count: 0,
+            last_run: world.last_change_tick(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            _marker: PhantomData,
+        };
+        state.update_archetypes(world);
+
+        Ok(state)
+    }
+
+    /// Caches the archetypes created after the last update
+    pub fn update_archetypes(&mut self, world: &World) {
+        assert_eq!(self.world_id, world.id(), "QueryState used with a different world");
+
+        let archetypes = world.archetypes();
+        for (index, archetype) in archetypes.iter().enumerate().skip(self.archetype_count) {
+            if Q::matches(archetype) && F::matches(archetype) {
+                self.archetypes.push((index, Q::state(archetype), F::state(archetype)));
+            }
+        }
+        self.archetype_count = archetypes.len();
+    }
+
+    pub fn iter<'w, 's>(&'s mut self, world: &'w mut World) -> QueryIter<'w, 's, Q, F> {
+        unsafe { self.iter_unchecked(world) }
+    }
+
+    /// Number of rows one task of [`QueryState::par_for_each`] processes.
+    /// Smaller batches balance uneven work better, larger ones have less
+    /// scheduling overhead.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Calls `f` for every item on the rayon pool, splitting the rows of each
+    /// archetype into batches
+    pub fn par_for_each<'w>(&mut self, world: &'w World, f: impl Fn(Q::Item<'w>) + Send + Sync)
+    where
+        Q: ReadOnlyQuery,
+    {
+        // SAFETY: the query only reads, which the shared borrow allows
+        unsafe { self.par_for_each_unchecked(world, f) }
+    }
+
+    /// Like [`QueryState::par_for_each`], but allows mutable queries. Every
+    /// row is visited by exactly one task, so writes never alias.
+    pub fn par_for_each_mut<'w>(&mut self, world: &'w mut World, f: impl Fn(Q::Item<'w>) + Send + Sync) {
+        unsafe { self.par_for_each_unchecked(world, f) }
+    }
+
+    /// # Safety
+    /// Same as [`QueryState::iter_unchecked`]
+    pub unsafe fn par_for_each_unchecked<'w>(&mut self, world: &'w World, f: impl Fn(Q::Item<'w>) + Send + Sync) {
+        self.update_archetypes(world);
+
+        let ticks = RunTicks {
+            last_run: self.last_run,
+            this_run: world.increment_change_tick(),
+        };
+        self.last_run = ticks.this_run;
+
+        let batch_size = self.batch_size;
+        let archetypes = world.archetypes();
+        let batches = self.archetypes
+            .iter()
+            .flat_map(|&(index, state, filter_state)| {
+                let archetype = &archetypes[index];
+                let fetch = SendFetch((
+                    Q::fetch(world, archetype, state, ticks),
+                    F::fetch(world, archetype, filter_state, ticks),
+                ));
+                let len = archetype.entities.len();
+
+                (0..len)
+                    .step_by(batch_size)
+                    .map(move |start| (fetch, start..len.min(start + batch_size)))
+            })
+            .collect::<Vec<_>>();
+
+        batches.into_par_iter().for_each(|(SendFetch((fetch, filter)), rows)| {
+            for row in rows {
+                if unsafe { Q::matches_row(fetch, row) && F::filter(filter, row) } {
+                    f(unsafe { Q::item(fetch, row) });
+                }
+            }
+        });
+    }
+
+    /// Components read or written by the query and its filter
+    pub fn access(&self) -> Vec<(ComponentId, Access)> {
+        let mut access = vec![];
+        Q::access(&mut access);
+        F::access(&mut access);
+        access
+    }
+
+    /// Iterates through a shared world, as done by systems running in parallel.
+    ///
+    /// # Safety
+    /// No other access to the components reported by [`QueryState::access`]
+    /// may conflict with the returned iterator while it is alive.
+    pub unsafe fn iter_unchecked<'w, 's>(&'s mut self, world: &'w World) -> QueryIter<'w, 's, Q, F> {
+        self.update_archetypes(world);
+
+        let ticks = RunTicks {
+            last_run: self.last_run,
+            this_run: world.increment_change_tick(),
+        };
+        self.last_run = ticks.this_run;
+
+        QueryIter {
+            world,
+            matched: self.archetypes.iter(),
+            ticks,
+            fetch: None,
+            row: 0,
+            len: 0,
+        }
+    }
+}
+
+const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// Fetched column pointers, shared by the tasks of a parallel iteration.
+/// The tasks visit disjoint rows, so sending the pointers is sound as long
+/// as the access of the query is valid.
+#[derive(Clone, Copy)]
+struct SendFetch<T>(T);
+
+unsafe impl<T> Send for SendFetch<T> {}
+
+unsafe impl<T> Sync for SendFetch<T> {}
+
+pub struct QueryIter<'w, 's, Q: Query, F: QueryFilter = ()> {
+    world: &'w World,
+    matched: std::slice::Iter<'s, (usize, Q::State, F::State)>,
+    ticks: RunTicks,
+    fetch: Option<(Q::Fetch, F::Fetch)>,
+    row: usize,
+    len: usize,
+}
+
+impl<'w, Q: Query, F: QueryFilter> Iterator for QueryIter<'w, '_, Q, F> {
+    type Item = Q::Item<'w>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((fetch, filter)) = self.fetch && self.row < self.len {
+                let row = self.row;
+                self.row += 1;
+
+                if unsafe { Q::matches_row(fetch, row) && F::filter(filter, row) } {
+                    return Some(unsafe { Q::item(fetch, row) });
+                }
+
+                continue;
+            }
+
+            let &(index, state, filter_state) = self.matched.next()?;
+            let archetype = &self.world.archetypes()[index];
+            self.fetch = Some((
+                Q::fetch(self.world, archetype, state, self.ticks),
+                F::fetch(self.world, archetype, filter_state, self.ticks),
+            ));
+            self.row = 0;
+            self.len = archetype.entities.len();
+        }
+    }
+}
+
+pub(super) fn column_index<T: Component>(archetype: &Archetype) -> Option<usize> {
+    archetype
+        .columns
+        .iter()
+        .position(|col| col.meta.id == T::component_id())
+}
+
+/// Rows of one component type in a fetched archetype
+pub enum ComponentFetch<T> {
+    Table {
+        data: *mut T,
+        ticks: *mut ComponentTicks,
+    },
+    Sparse {
+        set: *const SparseSet,
+        entities: *const EntityId,
+    },
+    Missing,
+}
+
+impl<T> Clone for ComponentFetch<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ComponentFetch<T> {}
+
+impl<T: Component> ComponentFetch<T> {
+    /// Fetches the column at `state`, or the sparse set of `T` if it is
+    /// stored in one
+    pub(super) fn new(world: &World, archetype: &Archetype, state: Option<usize>) -> Self {
+        if let Some(index) = state {
+            let col = &archetype.columns[index];
+            return ComponentFetch::Table {
+                data: col.ptr.as_ptr() as *mut T,
+                ticks: col.ticks_ptr(),
+            };
+        }
+
+        match world.sparse_set(T::component_id()) {
+            Some(set) if T::storage_type() == StorageType::Sparse => ComponentFetch::Sparse {
+                set,
+                entities: archetype.entities.as_ptr(),
+            },
+            _ => ComponentFetch::Missing,
+        }
+    }
+
+    /// Component and its ticks at `row`, if the entity has one
+    ///
+    /// # Safety
+    /// `row` must be in bounds of the fetched archetype.
+    pub(super) unsafe fn get(self, row: usize) -> Option<(*mut T, *mut ComponentTicks)> {
+        match self {
+            ComponentFetch::Table { data, ticks } => unsafe { Some((data.add(row), ticks.add(row))) },
+            ComponentFetch::Sparse { set, entities } => unsafe {
+                let set = &*set;
+                let dense = set.dense_index(*entities.add(row))?;
+                Some((set.get_ptr_at(dense) as *mut T, set.ticks_ptr_at(dense)))
+            },
+            ComponentFetch::Missing => None,
+        }
+    }
+}
+
+/// Archetype components are matched by mask, sparse ones by every archetype
+pub(super) fn matches_component<T: Component>(archetype: &Archetype) -> bool {
+    T::storage_type() == StorageType::Sparse || archetype.mask.has(T::component_id())
+}
+
+unsafe impl<T: Component + 'static> Query for &T {
+    type Item<'w> = &'w T;
+    type State = Option<usize>;
+    type Fetch = ComponentFetch<T>;
+
+    fn access(access: &mut Vec<(ComponentId, Access)>) {
+        access.push((T::component_id(), READ));
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        matches_component::<T>(archetype)
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, _ticks: RunTicks) -> Self::Fetch {
+        ComponentFetch::new(world, archetype, state)
+    }
+
+    unsafe fn matches_row(fetch: Self::Fetch, row: usize) -> bool {
+        unsafe { fetch.get(row).is_some() }
+    }
+
+    unsafe fn item<'w>(fetch: Self::Fetch, row: usize) -> Self::Item<'w> {
+        unsafe { &*fetch.get(row).unwrap_unchecked().0 }
+    }
+}
+
+unsafe impl<T: Component + 'static> ReadOnlyQuery for &T {}
+
+unsafe impl<T: Component + 'static> Query for &mut T {
+    type Item<'w> = Mut<'w, T>;
+    type State = Option<usize>;
+    type Fetch = (ComponentFetch<T>, RunTicks);
+
+    fn access(access: &mut Vec<(ComponentId, Access)>) {
+        access.push((T::component_id(), WRITE));
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        matches_component::<T>(archetype)
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch {
+        (ComponentFetch::new(world, archetype, state), ticks)
+    }
+
+    unsafe fn matches_row((fetch, _): Self::Fetch, row: usize) -> bool {
+        unsafe { fetch.get(row).is_some() }
+    }
+
+    unsafe fn item<'w>((fetch, run): Self::Fetch, row: usize) -> Self::Item<'w> {
+        unsafe {
+            let (data, ticks) = fetch.get(row).unwrap_unchecked();
+            Mut::new(&mut *data, &mut *ticks, run)
+        }
+    }
+}
+
+unsafe impl<T: Component + 'static> Query for Option<&T> {
+    type Item<'w> = Option<&'w T>;
+    type State = Option<usize>;
+    type Fetch = ComponentFetch<T>;
+
+    fn access(access: &mut Vec<(ComponentId, Access)>) {
+        access.push((T::component_id(), READ));
+    }
+
+    fn matches(_archetype: &Archetype) -> bool {
+        true
+    }
+
+    fn state(archetype: &Archetype) -> Self::State {
+        column_index::<T>(archetype)
+    }
+
+    fn fetch(world: &World, archetype: &Archetype, state: Self::State, _ticks: RunTicks) -> Self::Fetch {
+        ComponentFetch::new(world, archetype, state)
+    }
+
+    unsafe fn matches_row(_fetch: Self::Fetch, _row: usize) -> bool {
+        true
+    }
+
+    unsafe fn item<'w>(fetch: Self::Fetch, row: usize) -> Self::Item<'w> {
+        unsafe { fetch.get(row).map(|(data, _)| &*data) }
+    }
+}
+
+unsafe impl<T: Component + 'static> ReadOnlyQuery for Option<&T> {}
+
+unsafe impl Query for EntityId {
+    type Item<'w> = EntityId;
+    type State = ();
+    type Fetch = *const EntityId;
+
+    fn access(_access: &mut Vec<(ComponentId, Access)>) {}
+
+    fn matches(_archetype: &Archetype) -> bool {
+        true
+    }
+
+    fn state(_archetype: &Archetype) -> Self::State {}
+
+    fn fetch(_world: &World, archetype: &Archetype, _state: Self::State, _ticks: RunTicks) -> Self::Fetch {
+        archetype.entities.as_ptr()
+    }
+
+    unsafe fn matches_row(_fetch: Self::Fetch, _row: usize) -> bool {
+        true
+    }
+
+    unsafe fn item<'w>(fetch: Self::Fetch, row: usize) -> Self::Item<'w> {
+        unsafe { *fetch.add(row) }
+    }
+}
+
+unsafe impl ReadOnlyQuery for EntityId {}
+
+macro_rules! impl_query_tuple {
+    ($($name:ident),+) => {
+        unsafe impl<$($name: Query),+> Query for ($($name,)+) {
+            type Item<'w> = ($($name::Item<'w>,)+);
+            type State = ($($name::State,)+);
+            type Fetch = ($($name::Fetch,)+);
+
+            fn access(access: &mut Vec<(ComponentId, Access)>) {
+                $($name::access(access);)+
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))&&+
+            }
+
+            fn state(archetype: &Archetype) -> Self::State {
+                ($($name::state(archetype),)+)
+            }
+
+            fn fetch(world: &World, archetype: &Archetype, state: Self::State, ticks: RunTicks) -> Self::Fetch {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = state;
+                ($($name::fetch(world, archetype, $name, ticks),)+)
+            }
+
+            unsafe fn matches_row(fetch: Self::Fetch, row: usize) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = fetch;
+                unsafe { $($name::matches_row($name, row))&&+ }
+            }
+
+            unsafe fn item<'w>(fetch: Self::Fetch, row: usize) -> Self::Item<'w> {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = fetch;
+                ($(unsafe { $name::item($name, row) },)+)
+            }
+        }
+
+        unsafe impl<$($name: ReadOnlyQuery),+> ReadOnlyQuery for ($($name,)+) {}
+    };
+}
+
+impl_query_tuple! { A }
+impl_query_tuple! { A, B }
+impl_query_tuple! { A, B, C }
+impl_query_tuple! { A, B, C, D }
+impl_query_tuple! { A, B, C, D, E }
+impl_query_tuple! { A, B, C, D, E, F }
+impl_query_tuple! { A, B, C, D, E, F, G }
+impl_query_tuple! { A, B, C, D, E, F, G, H }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J, K }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J, K, L }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N, O }
+impl_query_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::world::World;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    struct Position(i32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    struct Velocity(i32);
+
+    fn is_conflict<Q: Query>() -> bool {
+        matches!(
+            validate_access::<Q>(),
+            Err(EcsError::QueryConflict { component, .. }) if component == Position::component_id()
+        )
+    }
+
+    #[test]
+    fn aliasing_queries_are_rejected() {
+        assert!(is_conflict::<(&mut Position, &mut Position)>());
+        assert!(is_conflict::<(&Position, &mut Position)>());
+        assert!(is_conflict::<(&mut Position, Option<&Position>)>());
+
+        let mut world = World::new();
+        world.spawn((Position(1),));
+        assert!(world.try_query::<(&Position, &mut Position)>().is_err());
+    }
+
+    #[test]
+    fn disjoint_queries_pass() {
+        assert!(validate_access::<(&Position, &Position)>().is_ok());
+        assert!(validate_access::<(EntityId, &mut Position, Option<&Velocity>)>().is_ok());
+
+        let mut world = World::new();
+        let entity = world.spawn((Position(1), Velocity(2)));
+        world.spawn((Position(10),));
+
+        for (mut position, velocity) in world.query::<(&mut Position, &Velocity)>() {
+            position.0 += velocity.0;
+        }
+        assert_eq!(world.get::<Position>(entity), Some(&Position(3)));
+        assert_eq!(world.query::<(EntityId, &Velocity)>(), [(entity, &Velocity(2))]);
+    }
+}
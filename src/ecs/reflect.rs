@@ -0,0 +1,526 @@
+use std::{alloc::{Layout, dealloc}, any::{Any, TypeId}, collections::{HashMap, hash_map::Entry}, marker::PhantomData};
+
+use glam::{IVec2, IVec3, IVec4, Mat3, Mat4, Quat, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
+use ron::value::RawValue;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::render::TransformationType;
+
+use super::archetype::EntityId;
+use super::component::{Component, ComponentId, ErasedComponent, StableId};
+use super::error::EcsError;
+use super::scene::MapEntities;
+use super::world::World;
+
+pub use kvantuma_derive::Reflect;
+
+/// Name and type of one field of a reflected struct
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub type_name: &'static str,
+}
+
+/// Shape of a reflected type. Values like numbers or strings have no fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeInfo {
+    pub type_name: &'static str,
+    pub fields: Vec<FieldInfo>,
+}
+
+/// Runtime access to the fields of a value by name, usually derived with
+/// `#[derive(Reflect)]`. Fields of tuple structs and elements of vectors
+/// are named by their index.
+pub trait Reflect: Any + Send + Sync {
+    fn reflect_type_info() -> TypeInfo where Self: Sized;
+
+    fn type_info(&self) -> TypeInfo;
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect>;
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect>;
+
+    /// Overwrites the value with `value`, field by field for structs.
+    /// Returns `false` if the types do not match.
+    fn apply(&mut self, value: &dyn Reflect) -> bool;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl dyn Reflect {
+    /// Nested field by a dot-separated path, e.g. `transform.translation.x`
+    pub fn path(&self, path: &str) -> Option<&dyn Reflect> {
+        path.split('.')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |value, name| value.field(name))
+    }
+
+    pub fn path_mut(&mut self, path: &str) -> Option<&mut dyn Reflect> {
+        path.split('.')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |value, name| value.field_mut(name))
+    }
+
+    /// Applies `value` to the field at `path`
+    pub fn set_path(&mut self, path: &str, value: &dyn Reflect) -> bool {
+        self.path_mut(path).is_some_and(|field| field.apply(value))
+    }
+
+    pub fn downcast_ref<T: Reflect>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Reflect>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+}
+
+/// Implements [`Reflect`] for `Clone` types without reflected fields, like
+/// enums or handles
+#[macro_export]
+macro_rules! reflect_value {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $crate::ecs::reflect::Reflect for $ty {
+                fn reflect_type_info() -> $crate::ecs::reflect::TypeInfo {
+                    $crate::ecs::reflect::TypeInfo {
+                        type_name: std::any::type_name::<Self>(),
+                        fields: vec![],
+                    }
+                }
+
+                fn type_info(&self) -> $crate::ecs::reflect::TypeInfo {
+                    <Self as $crate::ecs::reflect::Reflect>::reflect_type_info()
+                }
+
+                fn field(&self, _name: &str) -> Option<&dyn $crate::ecs::reflect::Reflect> {
+                    None
+                }
+
+                fn field_mut(&mut self, _name: &str) -> Option<&mut dyn $crate::ecs::reflect::Reflect> {
+                    None
+                }
+
+                fn apply(&mut self, value: &dyn $crate::ecs::reflect::Reflect) -> bool {
+                    match value.as_any().downcast_ref::<Self>() {
+                        Some(value) => {
+                            *self = value.clone();
+                            true
+                        }
+                        None => false,
+                    }
+                }
+
+                fn as_any(&self) -> &dyn std::any::Any {
+                    self
+                }
+
+                fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+                    self
+                }
+
+                fn into_any(self: Box<Self>) -> Box<dyn std::any::Any> {
+                    self
+                }
+            }
+        )+
+    };
+}
+
+reflect_value! {
+    bool, char, String,
+    u8, u16, u32, u64, u128, usize,
+    i8, i16, i32, i64, i128, isize,
+    f32, f64,
+    Vec2, Vec3, Vec4, UVec2, UVec3, UVec4, IVec2, IVec3, IVec4,
+    Quat, Mat3, Mat4,
+    EntityId, TransformationType,
+}
+
+impl<T: Reflect + Clone> Reflect for Vec<T> {
+    fn reflect_type_info() -> TypeInfo {
+        TypeInfo {
+            type_name: std::any::type_name::<Self>(),
+            fields: vec![],
+        }
+    }
+
+    fn type_info(&self) -> TypeInfo {
+        Self::reflect_type_info()
+    }
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        let item = self.get(name.parse::<usize>().ok()?)?;
+        Some(item)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        let item = self.get_mut(name.parse::<usize>().ok()?)?;
+        Some(item)
+    }
+
+    fn apply(&mut self, value: &dyn Reflect) -> bool {
+        match value.as_any().downcast_ref::<Self>() {
+            Some(value) => {
+                self.clone_from(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+pub type ReflectResult = Result<Box<dyn Reflect>, EcsError>;
+
+/// Serialization of a registered type through [`Reflect`] values
+#[derive(Clone, Copy)]
+pub struct ReflectSerde {
+    pub to_ron: fn(&dyn Reflect) -> Result<Box<RawValue>, EcsError>,
+    pub from_ron: fn(&RawValue) -> ReflectResult,
+    pub to_bytes: fn(&dyn Reflect) -> Result<Vec<u8>, EcsError>,
+    pub from_bytes: fn(&[u8]) -> ReflectResult,
+}
+
+/// Reflection data of one type, see [`TypeRegistry`]
+pub struct TypeRegistration {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub component_id: Option<ComponentId>,
+    /// Stable id of the component type, see [`Component::stable_id`]
+    pub stable_id: Option<StableId>,
+    pub info: TypeInfo,
+    pub default: Option<fn() -> Box<dyn Reflect>>,
+    pub serde: Option<ReflectSerde>,
+    pub clone: Option<CloneFn>,
+    /// Remaps entity references of a value, see [`MapEntities`]
+    pub map_entities: Option<MapEntitiesFn>,
+    component: Option<ReflectComponent>,
+    clone_raw: Option<RawCloneFn>,
+}
+
+pub type CloneFn = fn(&dyn Reflect) -> Box<dyn Reflect>;
+
+/// Clones the value behind the first pointer into the uninitialized memory
+/// behind the second one
+pub type RawCloneFn = unsafe fn(*const u8, *mut u8);
+
+pub type MapEntitiesFn = fn(&mut dyn Reflect, &mut dyn FnMut(EntityId) -> EntityId);
+
+type InsertFn = fn(&mut World, EntityId, Box<dyn Reflect>) -> Result<(), EcsError>;
+
+/// Typed operations on components of a registered type
+#[derive(Clone, Copy)]
+struct ReflectComponent {
+    get: fn(&World, EntityId) -> Option<&dyn Reflect>,
+    get_mut: fn(&mut World, EntityId) -> Option<&mut dyn Reflect>,
+    insert: InsertFn,
+    remove: fn(&mut World, EntityId) -> Option<Box<dyn Reflect>>,
+    as_component: fn(&dyn Reflect) -> &dyn Component,
+}
+
+impl TypeRegistration {
+    /// Component of the entity, if the type is a component
+    pub fn get<'w>(&self, world: &'w World, entity: EntityId) -> Option<&'w dyn Reflect> {
+        (self.component?.get)(world, entity)
+    }
+
+    /// Component of the entity, marked as changed
+    pub fn get_mut<'w>(&self, world: &'w mut World, entity: EntityId) -> Option<&'w mut dyn Reflect> {
+        (self.component?.get_mut)(world, entity)
+    }
+
+    /// Inserts a value of the registered type as a component
+    pub fn insert(&self, world: &mut World, entity: EntityId, value: Box<dyn Reflect>) -> Result<(), EcsError> {
+        let component = self.component.ok_or(EcsError::NotAComponent(self.type_name.to_string()))?;
+        (component.insert)(world, entity, value)
+    }
+
+    pub fn remove(&self, world: &mut World, entity: EntityId) -> Option<Box<dyn Reflect>> {
+        (self.component?.remove)(world, entity)
+    }
+
+    pub fn create_default(&self) -> Option<Box<dyn Reflect>> {
+        self.default.map(|default| default())
+    }
+
+    /// Clone working on component storage, used for world snapshots
+    pub(super) fn clone_raw(&self) -> Option<RawCloneFn> {
+        self.clone_raw
+    }
+
+    /// Clones a value of the registered type
+    pub fn clone_value(&self, value: &dyn Reflect) -> Result<Box<dyn Reflect>, EcsError> {
+        if value.as_any().type_id() != self.type_id {
+            return Err(EcsError::ReflectMismatch(self.type_name.to_string()));
+        }
+
+        let clone = self.clone.ok_or(EcsError::NotCloneable(self.type_name.to_string()))?;
+        Ok(clone(value))
+    }
+}
+
+/// Reflection data of registered types, looked up by [`TypeId`], component
+/// id or a name which stays the same between builds
+#[derive(Default)]
+pub struct TypeRegistry {
+    registrations: HashMap<TypeId, TypeRegistration>,
+    names: HashMap<&'static str, TypeId>,
+    components: HashMap<ComponentId, TypeId>,
+    stable: HashMap<StableId, TypeId>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> TypeRegistry {
+        TypeRegistry::default()
+    }
+
+    /// Registers a reflected type, named by its type path. Registering a
+    /// type again returns its existing registration.
+    pub fn register<T: Reflect>(&mut self) -> TypeRegistrationBuilder<'_, T> {
+        let type_id = TypeId::of::<T>();
+
+        if let Entry::Vacant(entry) = self.registrations.entry(type_id) {
+            let type_name = std::any::type_name::<T>();
+            entry.insert(TypeRegistration {
+                type_id,
+                type_name,
+                component_id: None,
+                stable_id: None,
+                info: T::reflect_type_info(),
+                default: None,
+                serde: None,
+                clone: None,
+                map_entities: None,
+                component: None,
+                clone_raw: None,
+            });
+            self.names.insert(type_name, type_id);
+        }
+
+        TypeRegistrationBuilder {
+            registry: self,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn get(&self, type_id: TypeId) -> Option<&TypeRegistration> {
+        self.registrations.get(&type_id)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&TypeRegistration> {
+        self.registrations.get(self.names.get(name)?)
+    }
+
+    pub fn get_by_component(&self, id: ComponentId) -> Option<&TypeRegistration> {
+        self.registrations.get(self.components.get(&id)?)
+    }
+
+    pub fn get_by_stable(&self, stable: StableId) -> Option<&TypeRegistration> {
+        self.registrations.get(self.stable.get(&stable)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TypeRegistration> {
+        self.registrations.values()
+    }
+
+    /// Inserts values of registered component types with a single
+    /// archetype move. Nothing is inserted if one of them is not a
+    /// component.
+    pub fn insert_all(&self, world: &mut World, entity: EntityId, values: Vec<Box<dyn Reflect>>) -> Result<(), EcsError> {
+        self.insert_all_with(world, entity, values, &[])
+    }
+
+    /// Same as [`TypeRegistry::insert_all`], inserting `extra` components
+    /// in the same move. Their data is only moved into the world on success.
+    pub(super) fn insert_all_with(
+        &self,
+        world: &mut World,
+        entity: EntityId,
+        values: Vec<Box<dyn Reflect>>,
+        extra: &[ErasedComponent],
+    ) -> Result<(), EcsError> {
+        let mut components = Vec::with_capacity(values.len() + extra.len());
+        for value in &values {
+            let component = self
+                .get(value.as_any().type_id())
+                .and_then(|registration| registration.component)
+                .ok_or_else(|| EcsError::NotAComponent(value.type_info().type_name.to_string()))?;
+            components.push(ErasedComponent::of((component.as_component)(&**value)));
+        }
+        components.extend_from_slice(extra);
+
+        world.insert_erased(entity, &components)?;
+
+        // The values were moved into the world bytewise, only their boxes
+        // are left to free
+        for value in values {
+            let layout = Layout::for_value(&*value);
+            let ptr = Box::into_raw(value);
+            if layout.size() != 0 {
+                unsafe { dealloc(ptr as *mut u8, layout) };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds optional capabilities to a [`TypeRegistration`]
+pub struct TypeRegistrationBuilder<'a, T> {
+    registry: &'a mut TypeRegistry,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Reflect> TypeRegistrationBuilder<'_, T> {
+    fn registration(&mut self) -> &mut TypeRegistration {
+        self.registry.registrations
+            .get_mut(&TypeId::of::<T>())
+            .expect("Builder is created for a registered type")
+    }
+
+    /// Replaces the type path as the stable name, e.g. to keep scene files
+    /// loadable after moving the type to another module
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        let registration = self.registration();
+        let old = std::mem::replace(&mut registration.type_name, name);
+
+        self.registry.names.remove(old);
+        self.registry.names.insert(name, TypeId::of::<T>());
+        self
+    }
+
+    pub fn with_default(mut self) -> Self
+    where
+        T: Default,
+    {
+        self.registration().default = Some(|| Box::new(T::default()));
+        self
+    }
+
+    pub fn with_serde(mut self) -> Self
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.registration().serde = Some(ReflectSerde {
+            to_ron: |value| {
+                RawValue::from_rust(downcast::<T>(value)?)
+                    .map_err(|e| serialization_error::<T>(e))
+            },
+            from_ron: |value| {
+                value
+                    .into_rust::<T>()
+                    .map(|value| Box::new(value) as Box<dyn Reflect>)
+                    .map_err(|e| serialization_error::<T>(e))
+            },
+            to_bytes: |value| {
+                bincode::serde::encode_to_vec(downcast::<T>(value)?, bincode::config::standard())
+                    .map_err(|e| serialization_error::<T>(e))
+            },
+            from_bytes: |bytes| {
+                bincode::serde::decode_from_slice::<T, _>(bytes, bincode::config::standard())
+                    .map(|(value, _)| Box::new(value) as Box<dyn Reflect>)
+                    .map_err(|e| serialization_error::<T>(e))
+            },
+        });
+        self
+    }
+
+    pub fn with_clone(mut self) -> Self
+    where
+        T: Clone,
+    {
+        self.registration().clone = Some(|value| {
+            let value = value
+                .downcast_ref::<T>()
+                .expect("Registration is used with values of its type");
+            Box::new(value.clone())
+        });
+        self.registration().clone_raw = Some(|src, dst| unsafe {
+            std::ptr::write(dst as *mut T, (*(src as *const T)).clone());
+        });
+        self
+    }
+
+    pub fn with_map_entities(mut self) -> Self
+    where
+        T: MapEntities,
+    {
+        self.registration().map_entities = Some(|value, map| {
+            if let Some(value) = value.downcast_mut::<T>() {
+                value.map_entities(map);
+            }
+        });
+        self
+    }
+
+    /// Enables access to components of the type through the registration
+    pub fn with_component(mut self) -> Self
+    where
+        T: Component,
+    {
+        let id = T::component_id();
+        let registration = self.registration();
+        registration.component_id = Some(id);
+        registration.stable_id = T::stable_id();
+        registration.component = Some(ReflectComponent {
+            get: |world, entity| world.get::<T>(entity).map(|value| value as &dyn Reflect),
+            get_mut: |world, entity| {
+                world
+                    .get_mut::<T>(entity)
+                    .map(|value| value.into_inner() as &mut dyn Reflect)
+            },
+            insert: |world, entity, value| {
+                let value = value
+                    .into_any()
+                    .downcast::<T>()
+                    .map_err(|_| EcsError::ReflectMismatch(std::any::type_name::<T>().to_string()))?;
+                world.insert(entity, (*value,))
+            },
+            remove: |world, entity| {
+                world
+                    .remove::<T>(entity)
+                    .map(|value| Box::new(value) as Box<dyn Reflect>)
+            },
+            as_component: |value| {
+                value
+                    .downcast_ref::<T>()
+                    .expect("Registration is used with values of its type")
+            },
+        });
+
+        self.registry.components.insert(id, TypeId::of::<T>());
+        if let Some(stable) = T::stable_id() {
+            self.registry.stable.insert(stable, TypeId::of::<T>());
+        }
+        self
+    }
+}
+
+fn downcast<T: Reflect>(value: &dyn Reflect) -> Result<&T, EcsError> {
+    value
+        .downcast_ref::<T>()
+        .ok_or_else(|| EcsError::ReflectMismatch(std::any::type_name::<T>().to_string()))
+}
+
+fn serialization_error<T>(error: impl std::fmt::Display) -> EcsError {
+    EcsError::Serialization {
+        type_name: std::any::type_name::<T>().to_string(),
+        message: error.to_string(),
+    }
+}
@@ -0,0 +1,126 @@
+use std::{any::{Any, TypeId}, cell::UnsafeCell, collections::HashMap, thread::{self, ThreadId}};
+
+struct ResourceCell(UnsafeCell<Box<dyn Any + Send + Sync>>);
+
+// SAFETY: the value is `Sync`, the cell is only written through
+// `Resources::get_unchecked_mut`, whose callers guarantee exclusive access
+unsafe impl Sync for ResourceCell {}
+
+/// Global data of a world, one value per type
+#[derive(Default)]
+pub struct Resources {
+    map: HashMap<TypeId, ResourceCell>,
+}
+
+impl Resources {
+    pub fn insert<R: Send + Sync + 'static>(&mut self, resource: R) -> Option<R> {
+        self.map
+            .insert(TypeId::of::<R>(), ResourceCell(UnsafeCell::new(Box::new(resource))))
+            .map(|old| *old.0.into_inner().downcast::<R>().unwrap())
+    }
+
+    pub fn get<R: 'static>(&self) -> Option<&R> {
+        self.map
+            .get(&TypeId::of::<R>())
+            .and_then(|r| unsafe { &*r.0.get() }.downcast_ref())
+    }
+
+    pub fn get_mut<R: 'static>(&mut self) -> Option<&mut R> {
+        self.map
+            .get_mut(&TypeId::of::<R>())
+            .and_then(|r| r.0.get_mut().downcast_mut())
+    }
+
+    /// # Safety
+    /// No other reference to the same resource may exist while the returned
+    /// one is alive.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_unchecked_mut<R: 'static>(&self) -> Option<&mut R> {
+        self.map
+            .get(&TypeId::of::<R>())
+            .and_then(|r| unsafe { &mut *r.0.get() }.downcast_mut())
+    }
+
+    pub fn remove<R: 'static>(&mut self) -> Option<R> {
+        self.map
+            .remove(&TypeId::of::<R>())
+            .map(|r| *r.0.into_inner().downcast::<R>().unwrap())
+    }
+
+    pub fn contains<R: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<R>())
+    }
+}
+
+/// Resources which cannot leave the thread that created the world, like
+/// audio or windowing handles. Every access panics on other threads.
+pub struct NonSendResources {
+    owner: ThreadId,
+    map: HashMap<TypeId, Box<dyn Any>>,
+}
+
+// SAFETY: the boxed values are only reachable through methods which check
+// that the caller runs on the owner thread
+unsafe impl Send for NonSendResources {}
+unsafe impl Sync for NonSendResources {}
+
+impl Default for NonSendResources {
+    fn default() -> Self {
+        Self {
+            owner: thread::current().id(),
+            map: HashMap::new(),
+        }
+    }
+}
+
+impl NonSendResources {
+    pub fn insert<R: 'static>(&mut self, resource: R) -> Option<R> {
+        self.check_thread::<R>();
+        self.map
+            .insert(TypeId::of::<R>(), Box::new(resource))
+            .map(|old| *old.downcast::<R>().unwrap())
+    }
+
+    pub fn get<R: 'static>(&self) -> Option<&R> {
+        self.check_thread::<R>();
+        self.map
+            .get(&TypeId::of::<R>())
+            .and_then(|r| r.downcast_ref())
+    }
+
+    pub fn get_mut<R: 'static>(&mut self) -> Option<&mut R> {
+        self.check_thread::<R>();
+        self.map
+            .get_mut(&TypeId::of::<R>())
+            .and_then(|r| r.downcast_mut())
+    }
+
+    pub fn remove<R: 'static>(&mut self) -> Option<R> {
+        self.check_thread::<R>();
+        self.map
+            .remove(&TypeId::of::<R>())
+            .map(|r| *r.downcast::<R>().unwrap())
+    }
+
+    pub fn contains<R: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<R>())
+    }
+
+    fn check_thread<R>(&self) {
+        assert_eq!(
+            thread::current().id(),
+            self.owner,
+            "Non-send resource {} accessed outside of its owner thread",
+            pretty_type_name::pretty_type_name::<R>(),
+        );
+    }
+}
+
+impl Drop for NonSendResources {
+    fn drop(&mut self) {
+        if !self.map.is_empty() && thread::current().id() != self.owner {
+            log::error!("Non-send resources dropped outside of their owner thread, leaking them");
+            std::mem::forget(std::mem::take(&mut self.map));
+        }
+    }
+}
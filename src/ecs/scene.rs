@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use ron::value::RawValue;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use slotmap::Key;
+
+use super::archetype::EntityId;
+use super::component::StableId;
+use super::error::EcsError;
+use super::reflect::{Reflect, ReflectResult, TypeRegistration, TypeRegistry};
+use super::world::World;
+
+/// Prefix of binary scenes, which tells them apart from RON ones on load
+const BINCODE_MAGIC: &[u8] = b"KVSCENE\0";
+
+/// Encoding of a saved world
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SceneFormat {
+    /// Human-readable, suitable for hand-edited level files
+    #[default]
+    Ron,
+    /// Compact binary, suitable for save games
+    Bincode,
+}
+
+/// Components which store ids of other entities. Registered with
+/// `TypeRegistrationBuilder::with_map_entities`, it lets loaded components
+/// point to the entities spawned for the saved ones.
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &mut dyn FnMut(EntityId) -> EntityId);
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+struct Scene<T> {
+    /// Stable ids of the saved component types, to find types which were
+    /// renamed since
+    #[serde(default)]
+    stable_ids: BTreeMap<String, StableId>,
+    entities: Vec<SceneEntity<T>>,
+}
+
+/// Components of one entity keyed by their registered type name
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+struct SceneEntity<T> {
+    id: EntityId,
+    components: BTreeMap<String, T>,
+}
+
+impl World {
+    /// Serializes every entity with its components registered with serde
+    /// in the [`TypeRegistry`] resource. Other components, like GPU handles,
+    /// are skipped with a warning.
+    pub fn save(&self, format: SceneFormat) -> Result<Vec<u8>, EcsError> {
+        let registry = self
+            .resource::<TypeRegistry>()
+            .ok_or(EcsError::NoSuchResource(std::any::type_name::<TypeRegistry>()))?;
+
+        let mut skipped = BTreeSet::new();
+        let bytes = match format {
+            SceneFormat::Ron => {
+                let scene = self.collect_scene(registry, &mut skipped, |registration, value| {
+                    (registration.serde.expect("Registration is serializable").to_ron)(value)
+                })?;
+
+                ron::ser::to_string_pretty(&scene, ron::ser::PrettyConfig::default())
+                    .map_err(|e| EcsError::InvalidScene(e.to_string()))?
+                    .into_bytes()
+            }
+            SceneFormat::Bincode => {
+                let scene = self.collect_scene(registry, &mut skipped, |registration, value| {
+                    (registration.serde.expect("Registration is serializable").to_bytes)(value)
+                })?;
+
+                let mut bytes = BINCODE_MAGIC.to_vec();
+                bincode::serde::encode_into_std_write(&scene, &mut bytes, bincode::config::standard())
+                    .map_err(|e| EcsError::InvalidScene(e.to_string()))?;
+                bytes
+            }
+        };
+
+        for name in skipped {
+            log::warn!("Component {name} is not serializable, skipping it");
+        }
+
+        Ok(bytes)
+    }
+
+    fn collect_scene<T>(
+        &self,
+        registry: &TypeRegistry,
+        skipped: &mut BTreeSet<String>,
+        serialize: impl Fn(&TypeRegistration, &dyn Reflect) -> Result<T, EcsError>,
+    ) -> Result<Scene<T>, EcsError> {
+        let mut stable_ids = BTreeMap::new();
+        let mut entities = vec![];
+
+        for entity in self.entities() {
+            let mut components = BTreeMap::new();
+
+            for id in self.entity_components(entity).into_iter().flatten() {
+                let Some(registration) = registry.get_by_component(id).filter(|r| r.serde.is_some()) else {
+                    skipped.insert(match registry.get_by_component(id) {
+                        Some(registration) => format!("`{}`", registration.type_name),
+                        None => format!("with id {id}"),
+                    });
+                    continue;
+                };
+
+                if let Some(stable) = registration.stable_id {
+                    stable_ids.insert(registration.type_name.to_string(), stable);
+                }
+
+                let value = registration.get(self, entity).expect("Entity has the component");
+                components.insert(registration.type_name.to_string(), serialize(registration, value)?);
+            }
+
+            entities.push(SceneEntity { id: entity, components });
+        }
+
+        Ok(Scene { stable_ids, entities })
+    }
+
+    /// Spawns the entities of a scene saved with [`World::save`] in either
+    /// format. Entity references of components registered with
+    /// `with_map_entities` are remapped to the spawned entities, references
+    /// to entities missing from the scene become null. Components are looked
+    /// up by their registered name, or by their stable id if their type was
+    /// renamed since the scene was saved.
+    ///
+    /// Returns the spawned entity for each saved one.
+    pub fn load(&mut self, bytes: &[u8]) -> Result<HashMap<EntityId, EntityId>, EcsError> {
+        self.resource_scope(|world, registry: &mut TypeRegistry| {
+            match bytes.strip_prefix(BINCODE_MAGIC) {
+                Some(bytes) => {
+                    let (scene, _) = bincode::serde::decode_from_slice::<Scene<Vec<u8>>, _>(bytes, bincode::config::standard())
+                        .map_err(|e| EcsError::InvalidScene(e.to_string()))?;
+
+                    world.spawn_scene(registry, scene, |registration, value| {
+                        (registration.serde.expect("Registration is serializable").from_bytes)(&value)
+                    })
+                }
+                None => {
+                    let scene = std::str::from_utf8(bytes)
+                        .map_err(|e| EcsError::InvalidScene(e.to_string()))
+                        .and_then(|s| ron::from_str::<Scene<Box<RawValue>>>(s)
+                            .map_err(|e| EcsError::InvalidScene(e.to_string()))
+                        )?;
+
+                    world.spawn_scene(registry, scene, |registration, value| {
+                        (registration.serde.expect("Registration is serializable").from_ron)(&value)
+                    })
+                }
+            }
+        })
+        .ok_or(EcsError::NoSuchResource(std::any::type_name::<TypeRegistry>()))?
+    }
+
+    fn spawn_scene<T>(
+        &mut self,
+        registry: &TypeRegistry,
+        scene: Scene<T>,
+        deserialize: impl Fn(&TypeRegistration, T) -> ReflectResult,
+    ) -> Result<HashMap<EntityId, EntityId>, EcsError> {
+        // Everything is deserialized before spawning, so that invalid scenes
+        // leave the world untouched
+        let mut entities = Vec::with_capacity(scene.entities.len());
+        for entity in scene.entities {
+            let mut values = vec![];
+            for (name, value) in entity.components {
+                let registration = registry
+                    .get_by_name(&name)
+                    .or_else(|| registry.get_by_stable(*scene.stable_ids.get(&name)?));
+
+                let Some(registration) = registration.filter(|r| r.serde.is_some()) else {
+                    log::warn!("Component `{name}` is not registered as serializable, skipping it");
+                    continue;
+                };
+
+                values.push((registration, deserialize(registration, value)?));
+            }
+            entities.push((entity.id, values));
+        }
+
+        let map = entities
+            .iter()
+            .map(|&(id, _)| (id, self.spawn_erased(&[])))
+            .collect::<HashMap<_, _>>();
+
+        let result = entities.into_iter().try_for_each(|(id, values)| {
+            let values = values
+                .into_iter()
+                .map(|(registration, mut value)| {
+                    if let Some(map_entities) = registration.map_entities {
+                        map_entities(&mut *value, &mut |id| map.get(&id).copied().unwrap_or_else(EntityId::null));
+                    }
+                    value
+                })
+                .collect();
+
+            registry.insert_all(self, map[&id], values)
+        });
+
+        if let Err(e) = result {
+            for &entity in map.values() {
+                self.despawn(entity);
+            }
+            return Err(e);
+        }
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::component::Component;
+    use crate::ecs::hierarchy::{Children, Parent};
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect, Serialize, Deserialize)]
+    struct Name(String);
+
+    /// Not registered, like a GPU handle
+    #[derive(Component)]
+    struct Handle;
+
+    /// Serializable, but not a component in every world
+    #[derive(Debug, Clone, PartialEq, Component, Reflect, Serialize, Deserialize)]
+    struct Score(u32);
+
+    fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Name>().with_serde().with_component();
+        registry.register::<Parent>().with_serde().with_map_entities().with_component();
+        registry.register::<Children>().with_default().with_serde().with_map_entities().with_component();
+        registry
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        for format in [SceneFormat::Ron, SceneFormat::Bincode] {
+            let mut world = World::new();
+            world.insert_resource(registry());
+            let root = world.spawn((Name("root".into()),));
+            let leaf = world.spawn((Name("leaf".into()), Handle));
+            world.set_parent(leaf, root).unwrap();
+            let bytes = world.save(format).unwrap();
+
+            let mut loaded = World::new();
+            loaded.insert_resource(registry());
+            loaded.spawn((Name("existing".into()),));
+            let map = loaded.load(&bytes).unwrap();
+
+            assert_eq!(map.len(), 2);
+            assert_eq!(loaded.entities().count(), 3);
+            assert_eq!(loaded.get::<Name>(map[&root]), Some(&Name("root".into())));
+            assert_eq!(loaded.get::<Name>(map[&leaf]), Some(&Name("leaf".into())));
+            assert!(!loaded.contains::<Handle>(map[&leaf]));
+            assert_eq!(loaded.parent(map[&leaf]), Some(map[&root]));
+            assert_eq!(loaded.children(map[&root]), [map[&leaf]]);
+        }
+    }
+
+    #[test]
+    fn failed_loads_despawn_spawned_entities() {
+        let mut world = World::new();
+        let mut types = registry();
+        types.register::<Score>().with_serde().with_component();
+        world.insert_resource(types);
+        world.spawn((Name("named".into()),));
+        world.spawn((Score(1),));
+        let bytes = world.save(SceneFormat::Ron).unwrap();
+
+        let mut loaded = World::new();
+        let mut types = registry();
+        types.register::<Score>().with_serde();
+        loaded.insert_resource(types);
+        let existing = loaded.spawn((Name("existing".into()),));
+
+        assert!(matches!(loaded.load(&bytes), Err(EcsError::NotAComponent(_))));
+        assert_eq!(loaded.entities().collect::<Vec<_>>(), [existing]);
+        assert_eq!(loaded.query::<&Name>(), [&Name("existing".into())]);
+    }
+}
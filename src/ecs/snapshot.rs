@@ -0,0 +1,110 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::archetype::{Column, Entities, EntityId};
+use super::component::ComponentId;
+use super::error::EcsError;
+use super::reflect::RawCloneFn;
+use super::sparse::SparseSet;
+use super::world::{World, WorldId};
+
+/// Copy of the entities and components of a world, taken with
+/// [`World::snapshot`] and restored with [`World::restore`]. Resources are
+/// not part of snapshots.
+pub struct WorldSnapshot {
+    pub(super) world_id: WorldId,
+    pub(super) archetypes: Vec<(Vec<Column>, Vec<EntityId>)>,
+    pub(super) sparse_sets: HashMap<ComponentId, SparseSet>,
+    pub(super) entities: Entities,
+    /// Clones of the components which cannot be copied bytewise
+    pub(super) clones: HashMap<ComponentId, RawCloneFn>,
+}
+
+impl WorldSnapshot {
+    pub fn world_id(&self) -> WorldId {
+        self.world_id
+    }
+
+    pub fn entity_count(&self) -> usize {
+        self.entities.len()
+    }
+}
+
+/// Ring buffer of the snapshots of the last frames, for rollback netcode.
+/// Pushing beyond the capacity drops the oldest snapshot.
+pub struct SnapshotBuffer {
+    capacity: usize,
+    snapshots: VecDeque<(u64, WorldSnapshot)>,
+}
+
+impl SnapshotBuffer {
+    pub fn new(capacity: usize) -> SnapshotBuffer {
+        assert!(capacity > 0, "Snapshot buffer must hold at least one snapshot");
+
+        SnapshotBuffer {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Stores the snapshot of `frame`, replacing the snapshots of the same or
+    /// later frames, which are outdated after a rollback
+    pub fn push(&mut self, frame: u64, snapshot: WorldSnapshot) {
+        while self.snapshots.back().is_some_and(|&(last, _)| last >= frame) {
+            self.snapshots.pop_back();
+        }
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((frame, snapshot));
+    }
+
+    /// Snapshots the world and stores it as `frame`
+    pub fn save(&mut self, frame: u64, world: &World) -> Result<(), EcsError> {
+        self.push(frame, world.snapshot()?);
+        Ok(())
+    }
+
+    pub fn get(&self, frame: u64) -> Option<&WorldSnapshot> {
+        self.snapshots
+            .iter()
+            .find(|&&(stored, _)| stored == frame)
+            .map(|(_, snapshot)| snapshot)
+    }
+
+    pub fn latest(&self) -> Option<(u64, &WorldSnapshot)> {
+        self.snapshots.back().map(|(frame, snapshot)| (*frame, snapshot))
+    }
+
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.snapshots.front().map(|&(frame, _)| frame)
+    }
+
+    /// Restores the world to `frame` and drops the snapshots of later frames
+    pub fn rollback(&mut self, world: &mut World, frame: u64) -> Result<(), EcsError> {
+        let snapshot = self.get(frame).ok_or(EcsError::NoSnapshot(frame))?;
+        world.restore(snapshot)?;
+
+        while self.snapshots.back().is_some_and(|&(last, _)| last > frame) {
+            self.snapshots.pop_back();
+        }
+
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
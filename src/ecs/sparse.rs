@@ -0,0 +1,221 @@
+use slotmap::Key;
+
+use super::archetype::{Column, EntityId};
+use super::component::{ComponentId, ComponentMeta};
+use super::reflect::RawCloneFn;
+use super::tick::{ComponentTicks, Tick};
+
+/// Where components of a type are stored. Archetype tables are fastest to
+/// iterate, sparse sets are cheapest to add and remove, because the entity
+/// does not change its archetype.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    #[default]
+    Table,
+    Sparse,
+}
+
+const EMPTY: u32 = u32::MAX;
+
+/// Components of one type stored densely, with an array indexed by entity
+/// slot pointing into the dense one
+pub struct SparseSet {
+    column: Column,
+    entities: Vec<EntityId>,
+    sparse: Vec<u32>,
+}
+
+impl SparseSet {
+    pub fn new(meta: ComponentMeta) -> SparseSet {
+        SparseSet {
+            column: Column::new(64, meta.id, meta.layout, meta.kind, meta.drop_fn),
+            entities: vec![],
+            sparse: vec![],
+        }
+    }
+
+    pub fn id(&self) -> ComponentId {
+        self.column.meta.id
+    }
+
+    pub fn meta(&self) -> &ComponentMeta {
+        &self.column.meta
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Entities having the component, in storage order
+    pub fn entities(&self) -> &[EntityId] {
+        &self.entities
+    }
+
+    pub fn contains(&self, entity: EntityId) -> bool {
+        self.dense_index(entity).is_some()
+    }
+
+    /// Index of the component of the entity in the dense array
+    pub fn dense_index(&self, entity: EntityId) -> Option<usize> {
+        let dense = *self.sparse.get(slot(entity))?;
+
+        (dense != EMPTY && self.entities[dense as usize] == entity).then_some(dense as usize)
+    }
+
+    pub fn get_ptr(&self, entity: EntityId) -> Option<*mut u8> {
+        self.dense_index(entity).map(|dense| self.get_ptr_at(dense))
+    }
+
+    /// Change ticks of the component, writable through shared references
+    pub fn ticks_ptr(&self, entity: EntityId) -> Option<*mut ComponentTicks> {
+        self.dense_index(entity).map(|dense| self.ticks_ptr_at(dense))
+    }
+
+    pub fn get_ptr_at(&self, dense: usize) -> *mut u8 {
+        self.column.get_ptr(dense)
+    }
+
+    pub fn ticks_ptr_at(&self, dense: usize) -> *mut ComponentTicks {
+        assert!(dense < self.len(), "Sparse set index {dense} out of bounds ({})", self.len());
+
+        unsafe { self.column.ticks_ptr().add(dense) }
+    }
+
+    /// Copies the component at `data` into the set, dropping the one the
+    /// entity already has.
+    ///
+    /// # Safety
+    /// Same as [`Column::push_raw`].
+    pub unsafe fn insert_raw(&mut self, entity: EntityId, data: *const u8, tick: Tick) {
+        if let Some(dense) = self.dense_index(entity) {
+            unsafe { self.column.replace_raw(dense, data, tick) };
+            return;
+        }
+
+        let slot = slot(entity);
+        if slot >= self.sparse.len() {
+            self.sparse.resize(slot + 1, EMPTY);
+        }
+        self.sparse[slot] = self.entities.len() as u32;
+
+        unsafe { self.column.push_raw(data, ComponentTicks::new(tick)) };
+        self.entities.push(entity);
+    }
+
+    /// Copy of the set, see [`Column::clone_with`].
+    ///
+    /// # Safety
+    /// Same as [`Column::clone_with`].
+    pub unsafe fn clone_with(&self, clone: Option<RawCloneFn>) -> SparseSet {
+        SparseSet {
+            column: unsafe { self.column.clone_with(clone) },
+            entities: self.entities.clone(),
+            sparse: self.sparse.clone(),
+        }
+    }
+
+    /// Drops the components of every entity
+    pub fn clear(&mut self) {
+        self.column.clear();
+        self.entities.clear();
+        self.sparse.clear();
+    }
+
+    /// Drops the component of the entity. Returns `false` if it has none.
+    pub fn remove(&mut self, entity: EntityId) -> bool {
+        let Some(dense) = self.dense_index(entity) else {
+            return false;
+        };
+
+        self.column.swap_remove(dense);
+        self.swap_remove_entity(dense);
+        true
+    }
+
+    /// Same as [`SparseSet::remove`], but the component is not dropped, so
+    /// its ownership must have been taken before.
+    pub fn remove_forget(&mut self, entity: EntityId) -> bool {
+        let Some(dense) = self.dense_index(entity) else {
+            return false;
+        };
+
+        self.column.swap_remove_forget(dense);
+        self.swap_remove_entity(dense);
+        true
+    }
+
+    fn swap_remove_entity(&mut self, dense: usize) {
+        let removed = self.entities.swap_remove(dense);
+        self.sparse[slot(removed)] = EMPTY;
+
+        if let Some(&moved) = self.entities.get(dense) {
+            self.sparse[slot(moved)] = dense as u32;
+        }
+    }
+}
+
+/// Index of the slot of the entity, stored in the low half of its key
+fn slot(entity: EntityId) -> usize {
+    (entity.data().as_ffi() & u32::MAX as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ecs::component::Component;
+    use crate::ecs::world::World;
+
+    #[derive(Component, Clone, Copy, Debug, PartialEq)]
+    struct Position(i32);
+
+    #[derive(Component, Clone, Copy, Debug, PartialEq)]
+    struct Velocity(i32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    #[component(sparse)]
+    struct Tag(String);
+
+    #[test]
+    fn sparse_components_survive_table_moves() {
+        let mut world = World::new();
+        let a = world.spawn((Position(1), Tag("a".into())));
+        let b = world.spawn((Position(2), Tag("b".into())));
+        let archetype = world.location(a).unwrap().archetype;
+        assert_eq!(world.archetypes()[archetype].columns.len(), 1);
+
+        world.insert(a, (Velocity(3),)).unwrap();
+        assert_ne!(world.location(a).unwrap().archetype, archetype);
+        assert_eq!(world.get::<Tag>(a), Some(&Tag("a".into())));
+
+        world.remove::<Position>(a);
+        world.remove::<Velocity>(a);
+        assert_eq!(world.get::<Tag>(a), Some(&Tag("a".into())));
+        assert_eq!(world.get::<Tag>(b), Some(&Tag("b".into())));
+    }
+
+    #[test]
+    fn sparse_changes_keep_the_archetype() {
+        let mut world = World::new();
+        let a = world.spawn((Position(1),));
+        let b = world.spawn((Position(2),));
+        let location = world.location(b);
+
+        world.insert(a, (Tag("a".into()),)).unwrap();
+        world.insert(b, (Tag("b".into()),)).unwrap();
+        assert_eq!(world.location(b), location);
+
+        assert_eq!(world.remove::<Tag>(a), Some(Tag("a".into())));
+        assert_eq!(world.location(b), location);
+        assert_eq!(world.get::<Tag>(b), Some(&Tag("b".into())));
+        assert_eq!(world.get::<Position>(b), Some(&Position(2)));
+
+        // A later entity in the same slot does not inherit the component
+        world.despawn(b);
+        let c = world.spawn((Position(3),));
+        assert_eq!(world.get::<Tag>(c), None);
+        assert_eq!(world.entity_components(c).unwrap().count(), 1);
+    }
+}
@@ -1,15 +1,637 @@
-use crate::ecs::world::World;
+use std::{any::TypeId, marker::PhantomData, ops::Range, ptr::NonNull};
 
-struct TemporaryXastData {
+use super::archetype::EntityId;
+use super::command::Commands;
+use super::component::{Component, ComponentId};
+use super::error::EcsError;
+use super::event::{EventWriter, Events};
+use super::filter::QueryFilter;
+use super::query::{Query, QueryIter, QueryState};
+use super::tick::Mut;
+use super::world::{Access, READ, WRITE, World};
 
+/// Components and resources a system reads or writes. Systems whose access
+/// is compatible run in parallel.
+#[derive(Debug, Clone, Default)]
+pub struct SystemAccess {
+    components: Vec<(ComponentId, Access)>,
+    resources: Vec<(TypeId, Access)>,
+    exclusive: bool,
 }
 
-pub trait TemporaryXastWorld {
-    fn spawn(&mut self, data: TemporaryXastData);
+impl SystemAccess {
+    pub fn new() -> SystemAccess {
+        SystemAccess::default()
+    }
 
-    // fn query(&mut self, query: &[TemporaryXastComponentQuery]) -> ...;
+    pub fn read<T: Component>(mut self) -> Self {
+        self.components.push((T::component_id(), READ));
+        self
+    }
+
+    pub fn write<T: Component>(mut self) -> Self {
+        self.components.push((T::component_id(), WRITE));
+        self
+    }
+
+    /// Declares every component accessed by the query
+    pub fn query<Q: Query>(self) -> Self {
+        self.query_filtered::<Q, ()>()
+    }
+
+    pub fn query_filtered<Q: Query, F: QueryFilter>(mut self) -> Self {
+        Q::access(&mut self.components);
+        F::access(&mut self.components);
+        self
+    }
+
+    pub fn read_resource<R: Send + Sync + 'static>(mut self) -> Self {
+        self.resources.push((TypeId::of::<R>(), READ));
+        self
+    }
+
+    pub fn write_resource<R: Send + Sync + 'static>(mut self) -> Self {
+        self.resources.push((TypeId::of::<R>(), WRITE));
+        self
+    }
+
+    pub fn read_events<T: Send + Sync + 'static>(self) -> Self {
+        self.read_resource::<Events<T>>()
+    }
+
+    pub fn write_events<T: Send + Sync + 'static>(self) -> Self {
+        self.write_resource::<Events<T>>()
+    }
+
+    /// Requests mutable access to the whole world. Exclusive systems run
+    /// alone on the thread calling [`Schedule::run`], so they can also use
+    /// non-send resources and spawn or despawn entities.
+    pub fn exclusive(mut self) -> Self {
+        self.exclusive = true;
+        self
+    }
+
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Whether both systems can run at the same time
+    pub fn is_compatible(&self, other: &SystemAccess) -> bool {
+        !self.exclusive
+            && !other.exclusive
+            && !conflicts(&self.components, &other.components)
+            && !conflicts(&self.resources, &other.resources)
+    }
+
+    fn allows<K: PartialEq>(&self, declared: &[(K, Access)], key: K, access: Access) -> bool {
+        self.exclusive || declared
+            .iter()
+            .any(|(k, a)| *k == key && (*a == WRITE || access == READ))
+    }
+}
+
+fn conflicts<K: PartialEq>(a: &[(K, Access)], b: &[(K, Access)]) -> bool {
+    a.iter().any(|(ka, aa)| {
+        b.iter().any(|(kb, ab)| ka == kb && (*aa == WRITE || *ab == WRITE))
+    })
+}
+
+/// Unit of game logic run by a [`Schedule`]
+pub trait System: Send {
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Everything the system may touch in [`System::execute`]. Accessing
+    /// anything else through [`SystemWorld`] panics.
+    fn access(&self) -> SystemAccess;
+
+    /// Called once with the whole world before the first run, e.g. to
+    /// create query states
+    fn init(&mut self, _world: &mut World) {}
+
+    fn execute(&mut self, world: &mut SystemWorld<'_>);
+}
+
+/// View of the world restricted to the declared [`SystemAccess`] of the
+/// running system
+pub struct SystemWorld<'w> {
+    world: NonNull<World>,
+    access: &'w SystemAccess,
+    system: &'w str,
+    commands: &'w mut Commands,
+    _marker: PhantomData<&'w World>,
+}
+
+// SAFETY: behaves like `&World`, or `&mut World` for exclusive systems.
+// Methods taking `&self` only read data, which is `Sync`.
+unsafe impl Send for SystemWorld<'_> {}
+unsafe impl Sync for SystemWorld<'_> {}
+
+impl<'w> SystemWorld<'w> {
+    fn shared(
+        world: &'w World,
+        access: &'w SystemAccess,
+        system: &'w str,
+        commands: &'w mut Commands,
+    ) -> SystemWorld<'w> {
+        SystemWorld {
+            world: NonNull::from(world),
+            access,
+            system,
+            commands,
+            _marker: PhantomData,
+        }
+    }
+
+    fn exclusive(
+        world: &'w mut World,
+        access: &'w SystemAccess,
+        system: &'w str,
+        commands: &'w mut Commands,
+    ) -> SystemWorld<'w> {
+        SystemWorld {
+            world: NonNull::from(world),
+            access,
+            system,
+            commands,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn query_state<Q: Query>(&self) -> QueryState<Q> {
+        self.query_state_filtered()
+    }
+
+    pub fn query_state_filtered<Q: Query, F: QueryFilter>(&self) -> QueryState<Q, F> {
+        QueryState::new(unsafe { self.world.as_ref() })
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn query<'a, Q: Query, F: QueryFilter>(
+        &'a mut self,
+        state: &'a mut QueryState<Q, F>,
+    ) -> QueryIter<'a, 'a, Q, F> {
+        for (id, access) in state.access() {
+            self.check_component_id(id, access);
+        }
+
+        // SAFETY: the access is declared, so no system running in parallel
+        // conflicts with it, and the iterator borrows `self` mutably
+        unsafe { state.iter_unchecked(self.world.as_ref()) }
+    }
+
+    /// Parallel iteration on the rayon pool, see [`QueryState::par_for_each`]
+    pub fn par_for_each<'a, Q: Query, F: QueryFilter>(
+        &'a mut self,
+        state: &mut QueryState<Q, F>,
+        f: impl Fn(Q::Item<'a>) + Send + Sync,
+    ) {
+        for (id, access) in state.access() {
+            self.check_component_id(id, access);
+        }
+
+        // SAFETY: the access is declared and the items borrow `self` mutably
+        unsafe { state.par_for_each_unchecked(self.world.as_ref(), f) }
+    }
+
+    pub fn get<T: Component>(&self, entity: EntityId) -> Option<&T> {
+        self.check_component::<T>(READ);
+        unsafe { self.world.as_ref() }.get(entity)
+    }
+
+    pub fn get_mut<T: Component>(&mut self, entity: EntityId) -> Option<Mut<'_, T>> {
+        self.check_component::<T>(WRITE);
+        // SAFETY: the write access is declared and the reference borrows
+        // `self` mutably
+        unsafe { self.world.as_ref().get_unchecked_mut(entity) }
+    }
+
+    pub fn resource<R: Send + Sync + 'static>(&self) -> Option<&R> {
+        self.check_resource::<R>(READ);
+        unsafe { self.world.as_ref() }.resource()
+    }
+
+    pub fn resource_mut<R: Send + Sync + 'static>(&mut self) -> Option<&mut R> {
+        self.check_resource::<R>(WRITE);
+        // SAFETY: the write access is declared and the reference borrows
+        // `self` mutably
+        unsafe { self.world.as_ref().resources().get_unchecked_mut() }
+    }
+
+    pub fn events<T: Send + Sync + 'static>(&self) -> Option<&Events<T>> {
+        self.resource()
+    }
+
+    pub fn event_writer<T: Send + Sync + 'static>(&mut self) -> Option<EventWriter<'_, T>> {
+        self.resource_mut::<Events<T>>().map(EventWriter::new)
+    }
+
+    /// Command buffer of the system, applied when the schedule run finishes
+    pub fn commands(&mut self) -> &mut Commands {
+        self.commands
+    }
+
+    /// Whole world of an exclusive system
+    pub fn world_mut(&mut self) -> &mut World {
+        assert!(
+            self.access.exclusive,
+            "System `{}` is not exclusive and cannot access the whole world",
+            self.system,
+        );
+
+        unsafe { self.world.as_mut() }
+    }
+
+    fn check_component<T: Component>(&self, access: Access) {
+        self.check_component_id(T::component_id(), access);
+    }
+
+    fn check_component_id(&self, id: ComponentId, access: Access) {
+        assert!(
+            self.access.allows(&self.access.components, id, access),
+            "System `{}` accesses component {id} ({access:?}) without declaring it",
+            self.system,
+        );
+    }
+
+    fn check_resource<R: 'static>(&self, access: Access) {
+        assert!(
+            self.access.allows(&self.access.resources, TypeId::of::<R>(), access),
+            "System `{}` accesses resource {} ({access:?}) without declaring it",
+            self.system,
+            pretty_type_name::pretty_type_name::<R>(),
+        );
+    }
+}
+
+/// System made of a closure, see [`system`]
+pub struct FunctionSystem<F> {
+    access: SystemAccess,
+    func: F,
+}
+
+/// Wraps a closure into a [`System`] with the given access
+pub fn system<F>(access: SystemAccess, func: F) -> FunctionSystem<F>
+where
+    F: FnMut(&mut SystemWorld<'_>) + Send + 'static,
+{
+    FunctionSystem { access, func }
+}
+
+impl<F> System for FunctionSystem<F>
+where
+    F: FnMut(&mut SystemWorld<'_>) + Send + 'static,
+{
+    fn name(&self) -> &str {
+        std::any::type_name::<F>()
+    }
+
+    fn access(&self) -> SystemAccess {
+        self.access.clone()
+    }
+
+    fn execute(&mut self, world: &mut SystemWorld<'_>) {
+        (self.func)(world)
+    }
+}
+
+/// System added to a [`Schedule`] together with its ordering constraints
+pub struct SystemDescriptor {
+    system: Box<dyn System>,
+    name: String,
+    access: SystemAccess,
+    labels: Vec<&'static str>,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    commands: Commands,
+    initialized: bool,
+}
+
+impl SystemDescriptor {
+    pub fn label(&mut self, label: &'static str) -> &mut Self {
+        self.labels.push(label);
+        self
+    }
+
+    /// Runs the system before every system with the label
+    pub fn before(&mut self, label: &'static str) -> &mut Self {
+        self.before.push(label);
+        self
+    }
+
+    /// Runs the system after every system with the label
+    pub fn after(&mut self, label: &'static str) -> &mut Self {
+        self.after.push(label);
+        self
+    }
+
+    fn run(&mut self, world: &World) {
+        let mut world = SystemWorld::shared(world, &self.access, &self.name, &mut self.commands);
+        self.system.execute(&mut world);
+    }
+
+    fn run_exclusive(&mut self, world: &mut World) {
+        let mut world = SystemWorld::exclusive(world, &self.access, &self.name, &mut self.commands);
+        self.system.execute(&mut world);
+    }
+}
+
+/// Ordered set of systems. Systems are grouped into batches, which run one
+/// after another, while systems of the same batch run in parallel on the
+/// rayon pool. Conflicting systems which could run at the same point keep
+/// the order in which they were added.
+///
+/// A schedule is a stage: the [`Commands`] recorded by its systems are
+/// applied in system order once all of them have run, so structural
+/// changes become visible to the next stage.
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<SystemDescriptor>,
+    batches: Vec<Range<usize>>,
+    dirty: bool,
+}
+
+impl Schedule {
+    pub fn new() -> Schedule {
+        Schedule::default()
+    }
+
+    pub fn add_system(&mut self, system: impl System + 'static) -> &mut SystemDescriptor {
+        self.dirty = true;
+        self.systems.push(SystemDescriptor {
+            name: system.name().to_string(),
+            access: system.access(),
+            system: Box::new(system),
+            labels: vec![],
+            before: vec![],
+            after: vec![],
+            commands: Commands::new(),
+            initialized: false,
+        });
+
+        self.systems.last_mut().unwrap()
+    }
+
+    /// Names of the systems in the order they run, one vector per batch
+    pub fn batches(&self) -> Vec<Vec<&str>> {
+        self.batches
+            .iter()
+            .map(|range| self.systems[range.clone()].iter().map(|s| s.name.as_str()).collect())
+            .collect()
+    }
+
+    /// Initializes new systems and rebuilds the batches if systems were
+    /// added. Called by [`Schedule::run`].
+    pub fn initialize(&mut self, world: &mut World) -> Result<(), EcsError> {
+        for descriptor in self.systems.iter_mut().filter(|s| !s.initialized) {
+            descriptor.system.init(world);
+            descriptor.initialized = true;
+        }
+
+        if self.dirty {
+            self.build()?;
+            self.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Runs every system once and applies their commands. The world tick is
+    /// left to the caller, so changes made by the systems are still seen by
+    /// later queries of the same frame, see [`World::advance_tick`].
+    pub fn run(&mut self, world: &mut World) -> Result<(), EcsError> {
+        self.initialize(world)?;
+
+        for range in self.batches.clone() {
+            let batch = &mut self.systems[range];
+
+            match batch {
+                [descriptor] if descriptor.access.exclusive => descriptor.run_exclusive(world),
+                [descriptor] => descriptor.run(world),
+                batch => {
+                    let world = &*world;
+                    rayon::scope(|scope| {
+                        for descriptor in batch {
+                            scope.spawn(move |_| descriptor.run(world));
+                        }
+                    });
+                }
+            }
+        }
+
+        self.apply_commands(world);
+
+        Ok(())
+    }
+
+    /// Applies the commands recorded by the systems, in the order they run
+    pub fn apply_commands(&mut self, world: &mut World) {
+        for descriptor in &mut self.systems {
+            descriptor.commands.apply(world);
+        }
+    }
+
+    fn build(&mut self) -> Result<(), EcsError> {
+        let count = self.systems.len();
+        let mut dependencies = vec![vec![]; count];
+
+        for (i, descriptor) in self.systems.iter().enumerate() {
+            for (labels, after) in [(&descriptor.after, true), (&descriptor.before, false)] {
+                for &label in labels {
+                    let others = self.labelled(label);
+                    if others.is_empty() {
+                        return Err(EcsError::UnknownLabel {
+                            system: descriptor.name.clone(),
+                            label: label.to_string(),
+                        });
+                    }
+
+                    for other in others {
+                        if after {
+                            dependencies[i].push(other);
+                        } else {
+                            dependencies[other].push(i);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut done = vec![false; count];
+        let mut order = Vec::with_capacity(count);
+        let mut batches = vec![];
+
+        while order.len() < count {
+            let ready: Vec<usize> = (0..count)
+                .filter(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]))
+                .collect();
+
+            let mut batch: Vec<usize> = vec![];
+            for (n, &i) in ready.iter().enumerate() {
+                let access = &self.systems[i].access;
+                let fits = batch.is_empty() || batch
+                    .iter()
+                    .all(|&b| access.is_compatible(&self.systems[b].access));
+                let waits = ready[..n]
+                    .iter()
+                    .filter(|r| !batch.contains(r))
+                    .any(|&r| !access.is_compatible(&self.systems[r].access));
+
+                if fits && !waits {
+                    batch.push(i);
+                }
+            }
+
+            if batch.is_empty() {
+                let names = (0..count)
+                    .filter(|&i| !done[i])
+                    .map(|i| self.systems[i].name.clone())
+                    .collect();
+
+                return Err(EcsError::ScheduleCycle(names));
+            }
+
+            batches.push(order.len()..order.len() + batch.len());
+            for &i in &batch {
+                done[i] = true;
+            }
+            order.extend(batch);
+        }
+
+        let mut systems: Vec<Option<SystemDescriptor>> = self.systems.drain(..).map(Some).collect();
+        self.systems = order
+            .into_iter()
+            .map(|i| systems[i].take().unwrap())
+            .collect();
+        self.batches = batches;
+
+        Ok(())
+    }
+
+    fn labelled(&self, label: &str) -> Vec<usize> {
+        self.systems
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.labels.contains(&label))
+            .map(|(i, _)| i)
+            .collect()
+    }
 }
 
-pub trait System {
-    fn execute(&self, world: &mut World);
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::ecs::filter::Changed;
+
+    struct Score;
+    struct Lives;
+
+    #[derive(Debug, PartialEq, Component)]
+    struct Health(u32);
+
+    /// Records its name into a shared log when it runs
+    struct Logged {
+        name: &'static str,
+        access: SystemAccess,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl System for Logged {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn access(&self) -> SystemAccess {
+            self.access.clone()
+        }
+
+        fn execute(&mut self, _world: &mut SystemWorld<'_>) {
+            self.log.lock().unwrap().push(self.name);
+        }
+    }
+
+    fn logged(name: &'static str, access: SystemAccess, log: &Arc<Mutex<Vec<&'static str>>>) -> Logged {
+        Logged { name, access, log: log.clone() }
+    }
+
+    #[test]
+    fn labels_order_systems() {
+        let log = Arc::default();
+        let mut schedule = Schedule::new();
+        schedule.add_system(logged("c", SystemAccess::new(), &log)).after("b");
+        schedule.add_system(logged("b", SystemAccess::new(), &log)).label("b").after("a");
+        schedule.add_system(logged("a", SystemAccess::new(), &log)).label("a");
+
+        let mut world = World::new();
+        schedule.run(&mut world).unwrap();
+        assert_eq!(schedule.batches(), [["a"], ["b"], ["c"]]);
+        assert_eq!(*log.lock().unwrap(), ["a", "b", "c"]);
+
+        schedule.add_system(logged("d", SystemAccess::new(), &log)).before("a");
+        schedule.run(&mut world).unwrap();
+        assert_eq!(schedule.batches(), [["d"], ["a"], ["b"], ["c"]]);
+    }
+
+    #[test]
+    fn unknown_labels_are_rejected() {
+        let log = Arc::default();
+        let mut schedule = Schedule::new();
+        schedule.add_system(logged("a", SystemAccess::new(), &log)).after("missing");
+
+        let result = schedule.run(&mut World::new());
+        assert!(matches!(result, Err(EcsError::UnknownLabel { system, label }) if system == "a" && label == "missing"));
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let log = Arc::default();
+        let mut schedule = Schedule::new();
+        schedule.add_system(logged("a", SystemAccess::new(), &log)).label("a").after("b");
+        schedule.add_system(logged("b", SystemAccess::new(), &log)).label("b").after("a");
+        schedule.add_system(logged("c", SystemAccess::new(), &log));
+
+        let result = schedule.run(&mut World::new());
+        assert!(matches!(result, Err(EcsError::ScheduleCycle(names)) if names == ["a", "b"]));
+    }
+
+    #[test]
+    fn conflicting_systems_run_in_separate_batches() {
+        let log = Arc::default();
+        let mut schedule = Schedule::new();
+        schedule.add_system(logged("write score", SystemAccess::new().write_resource::<Score>(), &log));
+        schedule.add_system(logged("read score", SystemAccess::new().read_resource::<Score>(), &log));
+        schedule.add_system(logged("write lives", SystemAccess::new().write_resource::<Lives>(), &log));
+        schedule.add_system(logged("exclusive", SystemAccess::new().exclusive(), &log));
+        schedule.add_system(logged("read lives", SystemAccess::new().read_resource::<Lives>(), &log));
+
+        schedule.run(&mut World::new()).unwrap();
+        assert_eq!(schedule.batches(), vec![
+            vec!["write score", "write lives"],
+            vec!["read score"],
+            vec!["exclusive"],
+            vec!["read lives"],
+        ]);
+        assert_eq!(log.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn system_writes_are_seen_by_later_changed_queries() {
+        let mut world = World::new();
+        let entity = world.spawn((Health(10),));
+        world.advance_tick();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(system(SystemAccess::new().write::<Health>(), move |world| {
+            world.get_mut::<Health>(entity).unwrap().0 -= 1;
+        }));
+        schedule.run(&mut world).unwrap();
+        assert_eq!(world.query_filtered::<&Health, Changed<Health>>(), [&Health(9)]);
+
+        world.advance_tick();
+        assert!(world.query_filtered::<&Health, Changed<Health>>().is_empty());
+    }
+}
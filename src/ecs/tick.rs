@@ -0,0 +1,91 @@
+use std::ops::{Deref, DerefMut};
+
+/// Monotonic counter of the world, used to detect component changes
+pub type Tick = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentTicks {
+    pub added: Tick,
+    pub changed: Tick,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: Tick) -> ComponentTicks {
+        ComponentTicks {
+            added: tick,
+            changed: tick,
+        }
+    }
+
+    pub fn is_added(&self, last_run: Tick, this_run: Tick) -> bool {
+        self.added > last_run && self.added <= this_run
+    }
+
+    pub fn is_changed(&self, last_run: Tick, this_run: Tick) -> bool {
+        self.changed > last_run && self.changed <= this_run
+    }
+}
+
+/// Window of ticks a query run reports changes for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunTicks {
+    pub last_run: Tick,
+    pub this_run: Tick,
+}
+
+/// Mutable reference to a component which marks it as changed when it is
+/// dereferenced mutably
+pub struct Mut<'w, T> {
+    value: &'w mut T,
+    ticks: &'w mut ComponentTicks,
+    run: RunTicks,
+}
+
+impl<'w, T> Mut<'w, T> {
+    pub fn new(value: &'w mut T, ticks: &'w mut ComponentTicks, run: RunTicks) -> Mut<'w, T> {
+        Mut { value, ticks, run }
+    }
+
+    pub fn is_added(&self) -> bool {
+        self.ticks.is_added(self.run.last_run, self.run.this_run)
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.ticks.is_changed(self.run.last_run, self.run.this_run)
+    }
+
+    pub fn set_changed(&mut self) {
+        self.ticks.changed = self.run.this_run;
+    }
+
+    /// Mutable access which does not mark the component as changed
+    pub fn bypass_change_detection(&mut self) -> &mut T {
+        self.value
+    }
+
+    pub fn into_inner(mut self) -> &'w mut T {
+        self.set_changed();
+        self.value
+    }
+}
+
+impl<T> Deref for Mut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> DerefMut for Mut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.set_changed();
+        self.value
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Mut<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
@@ -0,0 +1,189 @@
+use glam::{Mat4, Quat, Vec3};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::render::{Transformation, TransformationType};
+
+use super::archetype::EntityId;
+use super::component::Component;
+use super::filter::{Or, With, Without};
+use super::hierarchy::{Children, Parent};
+use super::query::QueryState;
+use super::reflect::Reflect;
+use super::system::{System, SystemAccess, SystemWorld};
+use super::world::World;
+
+/// Position of an entity relative to its parent, or to the world for
+/// roots. Rotation and scale are applied around `pivot`.
+#[derive(Debug, Clone, Copy, PartialEq, Component, Reflect, Serialize, Deserialize)]
+pub struct Transform {
+    pub transformation_type: TransformationType,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    pub pivot: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            transformation_type: TransformationType::default(),
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            pivot: Vec3::ZERO,
+        }
+    }
+}
+
+impl Transformation for Transform {
+    fn from_transform(
+        transformation_type: TransformationType,
+        translation: Vec3,
+        rotation: Quat,
+        pivot: Vec3,
+    ) -> Self {
+        Self {
+            transformation_type,
+            translation,
+            rotation,
+            pivot,
+            ..Default::default()
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    /// Local matrix, ordered according to the transformation type
+    pub fn matrix(&self) -> Mat4 {
+        let rotation_scale = Mat4::from_translation(self.pivot)
+            * Mat4::from_scale_rotation_translation(self.scale, self.rotation, Vec3::ZERO)
+            * Mat4::from_translation(-self.pivot);
+        let translation = Mat4::from_translation(self.translation);
+
+        match self.transformation_type {
+            TransformationType::FirstPerson => rotation_scale * translation,
+            TransformationType::LookAt => translation * rotation_scale,
+        }
+    }
+}
+
+/// World matrix of an entity, computed by [`TransformPropagation`] from the
+/// [`Transform`]s of the entity and its ancestors
+#[derive(Debug, Clone, Copy, PartialEq, Component, Reflect, Serialize, Deserialize)]
+pub struct GlobalTransform(pub Mat4);
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self(Mat4::IDENTITY)
+    }
+}
+
+/// Roots of hierarchies, and entities outside of any which have a
+/// [`Transform`]
+type RootFilter = (Without<Parent>, Or<(With<Transform>, With<Children>)>);
+
+/// Computes [`GlobalTransform`]s top-down, walking the hierarchies of
+/// different roots in parallel. Entities without a [`Transform`] pass the
+/// matrix of their parent through, roots without one the identity.
+#[derive(Default)]
+pub struct TransformPropagation {
+    roots: Option<QueryState<(EntityId, Option<&'static Transform>), RootFilter>>,
+}
+
+impl TransformPropagation {
+    pub fn new() -> TransformPropagation {
+        TransformPropagation::default()
+    }
+}
+
+impl System for TransformPropagation {
+    fn access(&self) -> SystemAccess {
+        SystemAccess::new()
+            .read::<Transform>()
+            .read::<Children>()
+            .read::<Parent>()
+            .write::<GlobalTransform>()
+    }
+
+    fn init(&mut self, world: &mut World) {
+        self.roots = Some(world.query_state_filtered());
+    }
+
+    fn execute(&mut self, world: &mut SystemWorld<'_>) {
+        let roots = self.roots.as_mut().expect("System is initialized");
+        let roots = world
+            .query(roots)
+            .map(|(entity, transform)| (entity, transform.map_or(Mat4::IDENTITY, Transform::matrix)))
+            .collect::<Vec<_>>();
+
+        let view = &*world;
+        let globals = roots
+            .par_iter()
+            .flat_map_iter(|&(root, matrix)| {
+                let mut globals = vec![(root, matrix)];
+                propagate(view, root, matrix, &mut globals);
+                globals
+            })
+            .collect::<Vec<_>>();
+
+        for (entity, matrix) in globals {
+            if let Some(mut global) = world.get_mut::<GlobalTransform>(entity)
+                && global.0 != matrix
+            {
+                global.0 = matrix;
+            }
+        }
+    }
+}
+
+fn propagate(world: &SystemWorld<'_>, entity: EntityId, matrix: Mat4, globals: &mut Vec<(EntityId, Mat4)>) {
+    let Some(children) = world.get::<Children>(entity) else {
+        return;
+    };
+
+    for &child in children.iter() {
+        let matrix = match world.get::<Transform>(child) {
+            Some(transform) => matrix * transform.matrix(),
+            None => matrix,
+        };
+
+        globals.push((child, matrix));
+        propagate(world, child, matrix, globals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::system::Schedule;
+
+    #[test]
+    fn propagation_computes_world_matrices() {
+        let mut world = World::new();
+        let root = world.spawn((Transform::from_translation(Vec3::X), GlobalTransform::default()));
+        let child = world.spawn((Transform::from_translation(Vec3::Y * 2.0), GlobalTransform::default()));
+        let passthrough = world.spawn((GlobalTransform::default(),));
+        let leaf = world.spawn((Transform::from_translation(Vec3::Z), GlobalTransform::default()));
+        world.set_parent(child, root).unwrap();
+        world.set_parent(passthrough, child).unwrap();
+        world.set_parent(leaf, passthrough).unwrap();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(TransformPropagation::new());
+        schedule.run(&mut world).unwrap();
+
+        let translation = |entity| world.get::<GlobalTransform>(entity).unwrap().0.w_axis.truncate();
+        assert_eq!(translation(root), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(translation(child), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(translation(passthrough), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(translation(leaf), Vec3::new(1.0, 2.0, 1.0));
+    }
+}
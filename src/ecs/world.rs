@@ -1,113 +1,1305 @@
+use std::{alloc::Layout, collections::{HashMap, HashSet}, sync::atomic::{AtomicU64, Ordering}};
+
+use slotmap::Key;
+
 use super::archetype::*;
 use super::component::*;
+use super::dynamic::{ComponentSchema, DynamicComponentInfo, DynamicComponents};
+use super::error::EcsError;
+use super::event::{EventWriter, Events};
+use super::hierarchy::{Children, Parent, register_hierarchy_hooks};
+use super::hook::{ComponentHooks, HookKind};
+use super::filter::QueryFilter;
+use super::query::{Query, QueryState, validate_access_list};
+use super::reflect::{RawCloneFn, TypeRegistration, TypeRegistry};
+use super::scene::MapEntities;
+use super::resource::{NonSendResources, Resources};
+use super::snapshot::WorldSnapshot;
+use super::sparse::{SparseSet, StorageType};
+use super::tick::{Mut, RunTicks, Tick};
+
+pub use kvantuma_derive::Bundle;
+
+/// Unique identifier of a world, used to tie cached state to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WorldId(u64);
+
+impl WorldId {
+    fn next() -> WorldId {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        WorldId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
 
-#[derive(Default)]
 pub struct World {
+    id: WorldId,
     archetypes: Vec<Archetype>,
-    next_entity: EntityId,
+    archetype_index: HashMap<ArchetypeMask, usize>,
+    entities: Entities,
+    resources: Resources,
+    non_send_resources: NonSendResources,
+    change_tick: AtomicU64,
+    last_change_tick: Tick,
+    event_updates: Vec<fn(&mut World)>,
+    hooks: HashMap<ComponentId, ComponentHooks>,
+    /// Components which have hooks, so that structural changes of the
+    /// others skip the hook lookups
+    hooked: ArchetypeMask,
+    stable_ids: StableIds,
+    /// Components whose stable id is registered, or which have none
+    checked: ArchetypeMask,
+    sparse_sets: HashMap<ComponentId, SparseSet>,
+    dynamic_components: DynamicComponents,
+}
+
+impl Default for World {
+    fn default() -> World {
+        let mut world = World {
+            id: WorldId::next(),
+            archetypes: vec![],
+            archetype_index: HashMap::new(),
+            entities: Entities::default(),
+            resources: Resources::default(),
+            non_send_resources: NonSendResources::default(),
+            change_tick: AtomicU64::new(1),
+            last_change_tick: 0,
+            event_updates: vec![],
+            hooks: HashMap::new(),
+            hooked: ArchetypeMask::default(),
+            stable_ids: StableIds::default(),
+            checked: ArchetypeMask::default(),
+            sparse_sets: HashMap::new(),
+            dynamic_components: DynamicComponents::default(),
+        };
+        register_hierarchy_hooks(&mut world);
+
+        world
+    }
 }
 
 impl World {
     pub fn new() -> World {
         World::default()
     }
+
+    pub fn id(&self) -> WorldId {
+        self.id
+    }
+
+    pub fn archetypes(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    /// Tick stamped on components changed directly through the world
+    pub fn change_tick(&self) -> Tick {
+        self.change_tick.load(Ordering::Acquire)
+    }
+
+    /// Tick before which changes are not reported to newly created queries
+    pub fn last_change_tick(&self) -> Tick {
+        self.last_change_tick
+    }
+
+    /// Starts a new run, returning its tick. Changes made during the run are
+    /// stamped with it, while later direct changes get a newer one.
+    pub fn increment_change_tick(&self) -> Tick {
+        self.change_tick.fetch_add(1, Ordering::AcqRel)
+    }
+
+    /// Ends a schedule run: changes made so far are no longer reported by
+    /// queries created afterwards. Cached queries keep their own last run.
+    pub fn advance_tick(&mut self) {
+        self.last_change_tick = self.increment_change_tick();
+    }
+
+    pub(super) fn resources(&self) -> &Resources {
+        &self.resources
+    }
 }
 
 impl World {
+    /// Spawns an entity with the components of the bundle. Panics if the
+    /// bundle contains a component type more than once, see
+    /// [`World::try_spawn`].
     pub fn spawn(&mut self, components: impl ComponentsBundle) -> EntityId {
+        self.try_spawn(components)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_spawn(&mut self, components: impl ComponentsBundle) -> Result<EntityId, EcsError> {
         let mut ids = vec![];
+        let mut table_ids = vec![];
 
         components.for_each(&mut |comp| {
             let id = comp.id();
             ids.push(id);
+            if comp.storage() == StorageType::Table {
+                table_ids.push(id);
+            }
         });
+        check_duplicates(&ids)?;
+        self.register_stable_ids(&components)?;
 
-        ids.sort();
+        table_ids.sort();
 
-        if let Some(archetype) = self
-            .archetypes
-            .iter_mut()
-            .find(|a| a.has_components(&ids)) 
-        {
-            components.for_each(&mut |comp| {
-                let id = comp.id();
-                let col = archetype
-                    .get_column_with_component_mut(id)
-                    .expect("Should have found column after bitset check");
-
-                col.push(comp);
-            });
-
-            let id = self.next_entity;
-            archetype.add_entity(id);
-            self.next_entity += 1;
-            
-            id
-        } else {
-            let mask = ArchetypeMask::from_ids(&ids);
+        let index = self.find_or_create_archetype(&table_ids, |_| {
             let mut columns = vec![];
             components.for_each(&mut |comp| {
-                let mut col = Column::new(64, comp.id(), comp.layout(), comp.kind(), comp.drop_fn());
-                col.push(comp);
-                columns.push(col);
+                if comp.storage() == StorageType::Table {
+                    columns.push(Column::new(64, comp.id(), comp.layout(), comp.kind(), comp.drop_fn()));
+                }
             });
+            columns
+        });
 
-            let mut archetype = Archetype::new(mask, columns);
+        let tick = self.change_tick();
+        let archetype = &mut self.archetypes[index];
+        components.for_each(&mut |comp| {
+            if comp.storage() == StorageType::Table {
+                archetype
+                    .get_column_with_component_mut(comp.id())
+                    .expect("Exact archetype must have a column for every component")
+                    .push(comp, tick);
+            }
+        });
 
-            let id = self.next_entity;
-            archetype.add_entity(id);
-            self.archetypes.push(archetype);
-            self.next_entity += 1;
-            
-            id
-        }
+        let id = self.entities.insert(EntityLocation {
+            archetype: index,
+            row: archetype.entities.len(),
+        });
+        archetype.add_entity(id);
+        archetype.debug_assert_columns();
+
+        components.for_each(&mut |comp| {
+            if comp.storage() == StorageType::Sparse {
+                unsafe {
+                    self.sparse_set_for(comp.meta())
+                        .insert_raw(id, comp as *const _ as *const u8, tick);
+                }
+            }
+        });
+
+        // Components were moved into the columns bytewise, their drop glue
+        // is now owned by the world
+        std::mem::forget(components);
+
+        self.trigger_hooks(HookKind::Add, id, &ids);
+
+        Ok(id)
     }
 
+    /// Same as [`World::spawn`] for type-erased components, which are stored
+    /// according to their [`StorageType`].
     pub fn spawn_erased(&mut self, components: &[ErasedComponent]) -> EntityId {
-        let mut ids = components
+        let ids = components
+            .iter()
+            .map(|comp| comp.id)
+            .collect::<Vec<_>>();
+        if let Err(e) = check_duplicates(&ids).and_then(|_| self.register_erased_stable_ids(components)) {
+            panic!("{e}");
+        }
+        let (sparse, table) = components
+            .iter()
+            .partition::<Vec<_>, _>(|comp| comp.storage == StorageType::Sparse);
+
+        let mut table_ids = table
             .iter()
             .map(|comp| comp.id)
             .collect::<Vec<_>>();
 
-        ids.sort();
+        table_ids.sort();
+
+        let index = self.find_or_create_archetype(&table_ids, |_| {
+            table
+                .iter()
+                .map(|comp| Column::new(64, comp.id, comp.layout, comp.kind, comp.drop_fn))
+                .collect()
+        });
 
-        if let Some(archetype) = self
-            .archetypes
-            .iter_mut()
-            .find(|a| a.has_components(&ids)) 
-        {
-            components.iter().for_each(|comp| {
-                let id = comp.id;
+        let tick = self.change_tick();
+        let archetype = &mut self.archetypes[index];
+        table.iter().for_each(|comp| {
+            archetype
+                .get_column_with_component_mut(comp.id)
+                .expect("Exact archetype must have a column for every component")
+                .push_erased(comp, tick);
+        });
+
+        let id = self.entities.insert(EntityLocation {
+            archetype: index,
+            row: archetype.entities.len(),
+        });
+        archetype.add_entity(id);
+        archetype.debug_assert_columns();
+
+        for comp in sparse {
+            unsafe { self.sparse_set_for(comp.meta()).insert_raw(id, comp.data, tick) };
+        }
+
+        self.trigger_hooks(HookKind::Add, id, &ids);
+
+        id
+    }
+
+    /// Spawns one entity per bundle. The archetype is resolved once and
+    /// every component type is copied into its column in one pass, which
+    /// makes this much faster than calling [`World::spawn`] in a loop.
+    /// Panics on duplicate components like [`World::spawn`].
+    pub fn spawn_batch<B: ComponentsBundle>(&mut self, bundles: impl IntoIterator<Item = B>) -> Vec<EntityId> {
+        let mut bundles = bundles.into_iter().collect::<Vec<_>>();
+        let Some(first) = bundles.first() else {
+            return vec![];
+        };
+
+        // Components by their offset in the bundle, which is the same for
+        // every value of the type as required by `ComponentsBundle`
+        let base = first as *const B as usize;
+        let mut ids = vec![];
+        let mut table_ids = vec![];
+        let mut fields = vec![];
+        first.for_each(&mut |comp| {
+            let offset = (comp as *const dyn Component as *const u8 as usize).wrapping_sub(base);
+            assert!(
+                offset.checked_add(comp.layout().size()).is_some_and(|end| end <= size_of::<B>()),
+                "Bundles must pass their own fields to `ComponentsBundle::for_each`",
+            );
+
+            ids.push(comp.id());
+            if comp.storage() == StorageType::Table {
+                table_ids.push(comp.id());
+            }
+            fields.push((comp.meta(), comp.storage(), offset));
+        });
+        if let Err(e) = check_duplicates(&ids).and_then(|_| self.register_stable_ids(first)) {
+            panic!("{e}");
+        }
+
+        table_ids.sort();
+
+        let index = self.find_or_create_archetype(&table_ids, |_| {
+            fields
+                .iter()
+                .filter(|&&(_, storage, _)| storage == StorageType::Table)
+                .map(|(meta, _, _)| Column::new(64, meta.id, meta.layout, meta.kind, meta.drop_fn))
+                .collect()
+        });
+
+        let tick = self.change_tick();
+        let count = bundles.len();
+        let stride = size_of::<B>();
+        let data = bundles.as_ptr() as *const u8;
+
+        let archetype = &mut self.archetypes[index];
+        for &(meta, storage, offset) in &fields {
+            if storage == StorageType::Table {
                 let col = archetype
-                    .get_column_with_component_mut(id)
-                    .expect("Should have found column after bitset check");
+                    .get_column_with_component_mut(meta.id)
+                    .expect("Exact archetype must have a column for every component");
+                unsafe { col.extend_strided(data.add(offset), stride, count, tick) };
+            }
+        }
 
-                col.push_erased(comp);
-            });
+        let first_row = archetype.entities.len();
+        let spawned = (0..count)
+            .map(|i| self.entities.insert(EntityLocation {
+                archetype: index,
+                row: first_row + i,
+            }))
+            .collect::<Vec<_>>();
+        archetype.entities.extend_from_slice(&spawned);
+        archetype.debug_assert_columns();
+
+        for &(meta, storage, offset) in &fields {
+            if storage == StorageType::Sparse {
+                let set = self.sparse_set_for(meta);
+                for (i, &entity) in spawned.iter().enumerate() {
+                    unsafe { set.insert_raw(entity, data.add(i * stride + offset), tick) };
+                }
+            }
+        }
+
+        // Components were moved into the world bytewise, only the memory of
+        // the bundles is left to free
+        unsafe { bundles.set_len(0) };
 
-            let id = self.next_entity;
-            archetype.add_entity(id);
-            self.next_entity += 1;
-            
-            id
+        if self.has_hooks(&ids) {
+            for &entity in &spawned {
+                self.trigger_hooks(HookKind::Add, entity, &ids);
+            }
+        }
+
+        spawned
+    }
+
+    /// Despawns every entity of the iterator, returning how many were alive
+    pub fn despawn_batch(&mut self, entities: impl IntoIterator<Item = EntityId>) -> usize {
+        entities
+            .into_iter()
+            .filter(|&entity| self.despawn(entity))
+            .count()
+    }
+
+    /// Despawns all entities. Archetypes, cached queries and resources stay
+    /// valid, so the world can be refilled quickly, e.g. when loading the
+    /// next level.
+    ///
+    /// Remove hooks run, except the ones of [`Parent`] and [`Children`],
+    /// which would only keep relationships between despawned entities in
+    /// sync.
+    pub fn clear(&mut self) {
+        let mut hooked = self.hooked.clone();
+        hooked.remove(Parent::component_id());
+        hooked.remove(Children::component_id());
+
+        if !hooked.is_empty() {
+            for entity in self.entities().collect::<Vec<_>>() {
+                let ids = self.entity_components(entity)
+                    .map(|ids| ids.filter(|&id| hooked.has(id)).collect::<Vec<_>>())
+                    .unwrap_or_default();
+                self.trigger_hooks(HookKind::Remove, entity, &ids);
+            }
+        }
+
+        for archetype in &mut self.archetypes {
+            archetype.clear();
+        }
+        for set in self.sparse_sets.values_mut() {
+            set.clear();
+        }
+        self.entities.clear();
+    }
+
+    /// Removes the entity and drops all of its components. Returns `false` if
+    /// the entity was already despawned.
+    pub fn despawn(&mut self, entity: EntityId) -> bool {
+        if self.entity_has_hooks(entity) {
+            let ids = self.entity_components(entity)
+                .map(|ids| ids.collect::<Vec<_>>())
+                .unwrap_or_default();
+            self.trigger_hooks(HookKind::Remove, entity, &ids);
+        }
+
+        let Some(location) = self.entities.remove(entity) else {
+            return false;
+        };
+
+        let archetype = &mut self.archetypes[location.archetype];
+        if let Some(moved) = archetype.swap_remove(location.row) {
+            self.entities[moved].row = location.row;
+        }
+        archetype.debug_assert_columns();
+
+        for set in self.sparse_sets.values_mut() {
+            set.remove(entity);
+        }
+
+        true
+    }
+
+    /// Adds the components to a live entity, replacing the ones it already
+    /// has, and moves it to the matching archetype.
+    pub fn insert(&mut self, entity: EntityId, components: impl ComponentsBundle) -> Result<(), EcsError> {
+        let mut ids = vec![];
+        components.for_each(&mut |comp| ids.push(comp.id()));
+        check_duplicates(&ids)?;
+        self.register_stable_ids(&components)?;
+        let added = if self.has_hooks(&ids) {
+            self.trigger_replace_hooks(entity, &ids)?
         } else {
-            let mask = ArchetypeMask::from_ids(&ids);
-            let mut columns = vec![];
-            components.iter().for_each(|comp| {
-                let mut col = Column::new(64, comp.id, comp.layout, comp.kind, comp.drop_fn);
-                col.push_erased(comp);
-                columns.push(col);
+            vec![]
+        };
+
+        let location = *self.entities
+            .get(entity)
+            .ok_or(EcsError::NoSuchEntity(entity))?;
+
+        let mut target = location.archetype;
+        components.for_each(&mut |comp| {
+            if comp.storage() == StorageType::Table {
+                target = self.archetype_with(target, comp.id(), || {
+                    Column::new(64, comp.id(), comp.layout(), comp.kind(), comp.drop_fn())
+                });
+            }
+        });
+
+        let row = if target != location.archetype {
+            self.move_entity(entity, location, target)
+        } else {
+            location.row
+        };
+
+        let tick = self.change_tick();
+        let archetype = &mut self.archetypes[target];
+        components.for_each(&mut |comp| {
+            if comp.storage() == StorageType::Sparse {
+                return;
+            }
+
+            let col = archetype
+                .get_column_with_component_mut(comp.id())
+                .expect("Target archetype must contain inserted component");
+
+            unsafe {
+                if col.len > row {
+                    col.replace_raw(row, comp as *const _ as *const u8, tick);
+                } else {
+                    col.push(comp, tick);
+                }
+            }
+        });
+        archetype.debug_assert_columns();
+
+        components.for_each(&mut |comp| {
+            if comp.storage() == StorageType::Sparse {
+                unsafe {
+                    self.sparse_set_for(comp.meta())
+                        .insert_raw(entity, comp as *const _ as *const u8, tick);
+                }
+            }
+        });
+
+        std::mem::forget(components);
+
+        self.trigger_hooks(HookKind::Add, entity, &added);
+
+        Ok(())
+    }
+
+    /// Takes the component out of a live entity and moves the entity to the
+    /// matching archetype. Returns `None` if the entity does not have it.
+    pub fn remove<T: Component>(&mut self, entity: EntityId) -> Option<T> {
+        let id = T::component_id();
+        self.trigger_remove_hooks(entity, id);
+
+        if T::storage_type() == StorageType::Sparse {
+            let set = self.sparse_sets.get_mut(&id)?;
+            let component = unsafe { std::ptr::read(set.get_ptr(entity)? as *const T) };
+            set.remove_forget(entity);
+
+            return Some(component);
+        }
+
+        let location = *self.entities.get(entity)?;
+
+        let col = self.archetypes[location.archetype].get_column_with_component(id)?;
+        let component = unsafe { std::ptr::read(col.get_ptr(location.row) as *const T) };
+
+        let target = self.archetype_without(location.archetype, id);
+        self.move_entity(entity, location, target);
+
+        Some(component)
+    }
+
+    /// Same as [`World::insert`] for type-erased components, whose data is
+    /// moved into the world.
+    pub fn insert_erased(&mut self, entity: EntityId, components: &[ErasedComponent]) -> Result<(), EcsError> {
+        let ids = components.iter().map(|comp| comp.id).collect::<Vec<_>>();
+        check_duplicates(&ids)?;
+        self.register_erased_stable_ids(components)?;
+        let added = if self.has_hooks(&ids) {
+            self.trigger_replace_hooks(entity, &ids)?
+        } else {
+            vec![]
+        };
+
+        let location = *self.entities
+            .get(entity)
+            .ok_or(EcsError::NoSuchEntity(entity))?;
+
+        let (sparse, table) = components
+            .iter()
+            .partition::<Vec<_>, _>(|comp| comp.storage == StorageType::Sparse);
+
+        let mut target = location.archetype;
+        for comp in &table {
+            target = self.archetype_with(target, comp.id, || {
+                Column::new(64, comp.id, comp.layout, comp.kind, comp.drop_fn)
             });
+        }
+
+        let row = if target != location.archetype {
+            self.move_entity(entity, location, target)
+        } else {
+            location.row
+        };
+
+        let tick = self.change_tick();
+        let archetype = &mut self.archetypes[target];
+        for comp in table {
+            let col = archetype
+                .get_column_with_component_mut(comp.id)
+                .expect("Target archetype must contain inserted component");
+
+            if col.len > row {
+                unsafe { col.replace_raw(row, comp.data, tick) };
+            } else {
+                col.push_erased(comp, tick);
+            }
+        }
+        archetype.debug_assert_columns();
+
+        for comp in sparse {
+            unsafe { self.sparse_set_for(comp.meta()).insert_raw(entity, comp.data, tick) };
+        }
+
+        self.trigger_hooks(HookKind::Add, entity, &added);
+
+        Ok(())
+    }
+
+    /// Drops the component with the given id and moves the entity to the
+    /// matching archetype. Returns `false` if the entity does not have it.
+    pub fn remove_erased(&mut self, entity: EntityId, id: ComponentId) -> bool {
+        self.trigger_remove_hooks(entity, id);
+
+        let Some(&location) = self.entities.get(entity) else {
+            return false;
+        };
+
+        if let Some(set) = self.sparse_sets.get_mut(&id) {
+            return set.remove(entity);
+        }
+
+        let Some(col) = self.archetypes[location.archetype].get_column_with_component(id) else {
+            return false;
+        };
+
+        if let Some(drop_fn) = col.meta.drop_fn {
+            unsafe { drop_fn(col.get_ptr(location.row)) };
+        }
+
+        let target = self.archetype_without(location.archetype, id);
+        self.move_entity(entity, location, target);
 
-            let mut archetype = Archetype::new(mask, columns);
+        true
+    }
+
+    pub fn is_alive(&self, entity: EntityId) -> bool {
+        self.entities.contains_key(entity)
+    }
+
+    pub fn location(&self, entity: EntityId) -> Option<EntityLocation> {
+        self.entities.get(entity).copied()
+    }
+
+    pub fn get<T: Component>(&self, entity: EntityId) -> Option<&T> {
+        if T::storage_type() == StorageType::Sparse {
+            let ptr = self.sparse_set(T::component_id())?.get_ptr(entity)?;
+            return Some(unsafe { &*(ptr as *const T) });
+        }
+
+        let location = self.entities.get(entity)?;
+        let col = self.archetypes[location.archetype]
+            .get_column_with_component(T::component_id())?;
+
+        Some(unsafe { &*(col.get_ptr(location.row) as *const T) })
+    }
+
+    pub fn get_mut<T: Component>(&mut self, entity: EntityId) -> Option<Mut<'_, T>> {
+        if T::storage_type() == StorageType::Sparse {
+            // SAFETY: the world is borrowed mutably
+            return unsafe { self.get_unchecked_mut(entity) };
+        }
+
+        let location = self.entities.get(entity)?;
+        let col = self.archetypes[location.archetype]
+            .get_column_with_component_mut(T::component_id())?;
+
+        let value = unsafe { &mut *(col.get_ptr(location.row) as *mut T) };
+        let run = RunTicks {
+            last_run: self.last_change_tick,
+            this_run: *self.change_tick.get_mut(),
+        };
+
+        Some(Mut::new(value, col.ticks[location.row].get_mut(), run))
+    }
+
+    /// # Safety
+    /// No other reference to the component may exist while the returned one
+    /// is alive.
+    pub(super) unsafe fn get_unchecked_mut<T: Component>(&self, entity: EntityId) -> Option<Mut<'_, T>> {
+        let (data, ticks) = if T::storage_type() == StorageType::Sparse {
+            let set = self.sparse_set(T::component_id())?;
+            (set.get_ptr(entity)?, set.ticks_ptr(entity)?)
+        } else {
+            let location = self.entities.get(entity)?;
+            let col = self.archetypes[location.archetype]
+                .get_column_with_component(T::component_id())?;
+
+            (col.get_ptr(location.row), unsafe { col.ticks_ptr().add(location.row) })
+        };
+
+        let run = RunTicks {
+            last_run: self.last_change_tick,
+            this_run: self.change_tick(),
+        };
+
+        unsafe { Some(Mut::new(&mut *(data as *mut T), &mut *ticks, run)) }
+    }
+
+    pub fn contains<T: Component>(&self, entity: EntityId) -> bool {
+        if T::storage_type() == StorageType::Sparse {
+            return self.sparse_set(T::component_id()).is_some_and(|set| set.contains(entity));
+        }
+
+        self.entities
+            .get(entity)
+            .is_some_and(|location| self.archetypes[location.archetype]
+                .has_components(&[T::component_id()])
+            )
+    }
+
+    /// Records the stable id of the component type, if it has one, so that
+    /// persisted data can refer to the component. Fails if another type
+    /// registered in this world has the same stable id. Components are
+    /// registered automatically when they are first spawned or inserted.
+    pub fn register_component<T: Component>(&mut self) -> Result<ComponentId, EcsError> {
+        let id = T::component_id();
+        if let Some(stable) = T::stable_id() {
+            self.stable_ids.insert(stable, id, std::any::type_name::<T>())?;
+        }
+        self.checked.insert(id);
+
+        if T::storage_type() == StorageType::Sparse {
+            self.sparse_set_for(ComponentMeta::of::<T>());
+        }
+
+        Ok(id)
+    }
 
-            let id = self.next_entity;
-            archetype.add_entity(id);
-            self.archetypes.push(archetype);
-            self.next_entity += 1;
-            
-            id
+    /// Defines a component type which does not exist in Rust, e.g. one
+    /// declared by a script. Its values are spawned and inserted as
+    /// [`ErasedComponent`]s, see [`DynamicComponentInfo::erased`], and
+    /// queried with [`QueryBuilder`](super::dynamic::QueryBuilder). The
+    /// stable id of the component is hashed from its name.
+    ///
+    /// Fails if the size of the layout is not a multiple of its alignment,
+    /// or if a field of the schema does not fit into it at an aligned
+    /// offset.
+    pub fn register_dynamic_component(
+        &mut self,
+        name: impl Into<String>,
+        layout: Layout,
+        drop_fn: Option<unsafe fn(*mut u8)>,
+        schema: ComponentSchema,
+    ) -> Result<ComponentId, EcsError> {
+        let name = name.into();
+        if self.dynamic_components.get_by_name(&name).is_some() {
+            return Err(EcsError::DynamicComponentExists(name));
         }
+        schema.validate(&name, layout)?;
+
+        let id = dynamic_component_id();
+        self.stable_ids.insert(StableId::from_name(&name), id, name.clone())?;
+        self.dynamic_components.insert(DynamicComponentInfo {
+            id,
+            name,
+            layout,
+            drop_fn,
+            schema,
+        });
+
+        Ok(id)
+    }
+
+    pub fn dynamic_components(&self) -> &DynamicComponents {
+        &self.dynamic_components
+    }
+
+    /// Stable id of a component used in or registered with this world
+    pub fn stable_id(&self, id: ComponentId) -> Option<StableId> {
+        self.stable_ids.stable_id(id)
+    }
+
+    /// Component registered under the stable id in this world
+    pub fn component_id_by_stable(&self, stable: StableId) -> Option<ComponentId> {
+        self.stable_ids.component_id(stable)
+    }
+
+    /// Registers the stable ids of components used for the first time,
+    /// see [`World::register_component`]
+    fn register_stable_ids(&mut self, components: &impl ComponentsBundle) -> Result<(), EcsError> {
+        let mut result = Ok(());
+        components.for_each(&mut |comp| {
+            if result.is_ok() {
+                result = self.register_stable_id(comp.id(), comp.stable().map(|stable| (stable, comp.type_name())));
+            }
+        });
+
+        result
+    }
+
+    /// Same as [`World::register_stable_ids`] for type-erased components
+    fn register_erased_stable_ids(&mut self, components: &[ErasedComponent]) -> Result<(), EcsError> {
+        components
+            .iter()
+            .try_for_each(|comp| self.register_stable_id(comp.id, comp.stable))
+    }
+
+    fn register_stable_id(&mut self, id: ComponentId, stable: Option<(StableId, &'static str)>) -> Result<(), EcsError> {
+        if self.checked.has(id) {
+            return Ok(());
+        }
+
+        if let Some((stable, name)) = stable {
+            self.stable_ids.insert(stable, id, name)?;
+        }
+        self.checked.insert(id);
+
+        Ok(())
+    }
+
+    /// All alive entities
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.entities.keys()
+    }
+
+    /// Ids of all components attached to the entity
+    pub fn entity_components(&self, entity: EntityId) -> Option<impl Iterator<Item = ComponentId> + '_> {
+        let location = self.entities.get(entity)?;
+
+        Some(self.archetypes[location.archetype]
+            .columns
+            .iter()
+            .map(|col| col.meta.id)
+            .chain(self.sparse_sets
+                .values()
+                .filter(move |set| set.contains(entity))
+                .map(SparseSet::id)
+            )
+        )
+    }
+
+    /// Sparse set of the component, if it is stored in one
+    pub fn sparse_set(&self, id: ComponentId) -> Option<&SparseSet> {
+        self.sparse_sets.get(&id)
+    }
+
+    fn sparse_set_for(&mut self, meta: ComponentMeta) -> &mut SparseSet {
+        self.sparse_sets
+            .entry(meta.id)
+            .or_insert_with(|| SparseSet::new(meta))
+    }
+}
+
+impl World {
+    /// Stores a global value, returning the previous one of the same type
+    pub fn insert_resource<R: Send + Sync + 'static>(&mut self, resource: R) -> Option<R> {
+        self.resources.insert(resource)
+    }
+
+    pub fn resource<R: Send + Sync + 'static>(&self) -> Option<&R> {
+        self.resources.get()
+    }
+
+    pub fn resource_mut<R: Send + Sync + 'static>(&mut self) -> Option<&mut R> {
+        self.resources.get_mut()
+    }
+
+    pub fn remove_resource<R: Send + Sync + 'static>(&mut self) -> Option<R> {
+        self.resources.remove()
+    }
+
+    pub fn contains_resource<R: Send + Sync + 'static>(&self) -> bool {
+        self.resources.contains::<R>()
+    }
+
+    /// Takes the resource out for the duration of `f`, so that it can be
+    /// used together with mutable access to the world. Returns `None` if the
+    /// resource does not exist.
+    pub fn resource_scope<R: Send + Sync + 'static, T>(
+        &mut self, 
+        f: impl FnOnce(&mut World, &mut R) -> T,
+    ) -> Option<T> {
+        let mut resource = self.remove_resource::<R>()?;
+        let result = f(self, &mut resource);
+        self.insert_resource(resource);
+
+        Some(result)
+    }
+
+    /// Stores a global value which is not thread safe. Non-send resources
+    /// can only be accessed from the thread which created the world.
+    pub fn insert_non_send_resource<R: 'static>(&mut self, resource: R) -> Option<R> {
+        self.non_send_resources.insert(resource)
+    }
+
+    pub fn non_send_resource<R: 'static>(&self) -> Option<&R> {
+        self.non_send_resources.get()
+    }
+
+    pub fn non_send_resource_mut<R: 'static>(&mut self) -> Option<&mut R> {
+        self.non_send_resources.get_mut()
+    }
+
+    pub fn remove_non_send_resource<R: 'static>(&mut self) -> Option<R> {
+        self.non_send_resources.remove()
+    }
+}
+
+impl World {
+    /// Creates the [`Events`] resource of the type, which is then updated
+    /// by [`World::update_events`]. Does nothing if it already exists.
+    pub fn add_event<T: Send + Sync + 'static>(&mut self) {
+        if self.contains_resource::<Events<T>>() {
+            return;
+        }
+
+        self.insert_resource(Events::<T>::default());
+        self.event_updates.push(|world| {
+            if let Some(events) = world.resource_mut::<Events<T>>() {
+                events.update();
+            }
+        });
+    }
+
+    /// # Panics
+    /// If the event type was not added with [`World::add_event`]
+    pub fn send_event<T: Send + Sync + 'static>(&mut self, event: T) {
+        self.resource_mut::<Events<T>>()
+            .unwrap_or_else(|| panic!(
+                "Event {} was not added to the world", 
+                pretty_type_name::pretty_type_name::<T>(),
+            ))
+            .send(event);
+    }
+
+    pub fn event_writer<T: Send + Sync + 'static>(&mut self) -> Option<EventWriter<'_, T>> {
+        self.resource_mut::<Events<T>>().map(EventWriter::new)
+    }
+
+    pub fn events<T: Send + Sync + 'static>(&self) -> Option<&Events<T>> {
+        self.resource()
+    }
+
+    /// Swaps the buffers of every added event type. Called once per update
+    /// by the app.
+    pub fn update_events(&mut self) {
+        for update in self.event_updates.clone() {
+            update(self);
+        }
+    }
+}
+
+impl World {
+    /// Hooks of the component with the given id, e.g. of a dynamic one
+    pub fn component_hooks_mut(&mut self, id: ComponentId) -> &mut ComponentHooks {
+        self.hooked.insert(id);
+        self.hooks.entry(id).or_default()
+    }
+
+    /// See [`ComponentHooks::on_add`]
+    pub fn on_add<T: Component>(&mut self, hook: impl Fn(&mut World, EntityId) + Send + Sync + 'static) {
+        self.component_hooks_mut(T::component_id()).on_add(hook);
+    }
+
+    /// See [`ComponentHooks::on_replace`]
+    pub fn on_replace<T: Component>(&mut self, hook: impl Fn(&mut World, EntityId) + Send + Sync + 'static) {
+        self.component_hooks_mut(T::component_id()).on_replace(hook);
+    }
+
+    /// See [`ComponentHooks::on_remove`]
+    pub fn on_remove<T: Component>(&mut self, hook: impl Fn(&mut World, EntityId) + Send + Sync + 'static) {
+        self.component_hooks_mut(T::component_id()).on_remove(hook);
+    }
+
+    /// Whether any of the components has hooks
+    fn has_hooks(&self, ids: &[ComponentId]) -> bool {
+        ids.iter().any(|&id| self.hooked.has(id))
+    }
+
+    /// Whether any component of the entity has hooks
+    fn entity_has_hooks(&self, entity: EntityId) -> bool {
+        self.entities.get(entity).is_some_and(|location| {
+            self.archetypes[location.archetype].mask.intersects(&self.hooked)
+                || self.sparse_sets
+                    .iter()
+                    .any(|(&id, set)| self.hooked.has(id) && set.contains(entity))
+        })
+    }
+
+    fn trigger_hooks(&mut self, kind: HookKind, entity: EntityId, ids: &[ComponentId]) {
+        if !self.has_hooks(ids) {
+            return;
+        }
+
+        let hooks = ids
+            .iter()
+            .filter_map(|id| self.hooks.get(id))
+            .flat_map(|hooks| hooks.get(kind).iter().cloned())
+            .collect::<Vec<_>>();
+
+        for hook in hooks {
+            hook(self, entity);
+        }
+    }
+
+    /// Runs the replace hooks of the components the entity already has,
+    /// returning the ones it does not have yet
+    fn trigger_replace_hooks(&mut self, entity: EntityId, ids: &[ComponentId]) -> Result<Vec<ComponentId>, EcsError> {
+        let location = *self.entities
+            .get(entity)
+            .ok_or(EcsError::NoSuchEntity(entity))?;
+
+        let mask = &self.archetypes[location.archetype].mask;
+        let (replaced, added) = ids
+            .iter()
+            .partition::<Vec<_>, _>(|&&id| mask.has(id) || self.sparse_set(id).is_some_and(|set| set.contains(entity)));
+        self.trigger_hooks(HookKind::Replace, entity, &replaced);
+
+        Ok(added)
+    }
+
+    fn trigger_remove_hooks(&mut self, entity: EntityId, id: ComponentId) {
+        if !self.hooked.has(id) {
+            return;
+        }
+
+        let has = match self.sparse_set(id) {
+            Some(set) => set.contains(entity),
+            None => self.entities.get(entity).is_some_and(|l| self.archetypes[l.archetype].mask.has(id)),
+        };
+
+        if has {
+            self.trigger_hooks(HookKind::Remove, entity, &[id]);
+        }
+    }
+}
+
+impl World {
+    /// Copies all entities and components, to be restored later with
+    /// [`World::restore`]. Components without drop glue are copied bytewise,
+    /// the others must be registered with `with_clone` in the
+    /// [`TypeRegistry`] resource.
+    pub fn snapshot(&self) -> Result<WorldSnapshot, EcsError> {
+        let registry = self.resource::<TypeRegistry>();
+        let mut clones = HashMap::new();
+        let mut clone_fn = |meta: &ComponentMeta, len: usize| -> Result<Option<RawCloneFn>, EcsError> {
+            if meta.drop_fn.is_none() || len == 0 {
+                return Ok(None);
+            }
+
+            let registration = registry.and_then(|registry| registry.get_by_component(meta.id));
+            let clone = registration
+                .and_then(TypeRegistration::clone_raw)
+                .ok_or_else(|| EcsError::NotCloneable(registration.map_or_else(
+                    || format!("component {}", meta.id),
+                    |registration| registration.type_name.to_string(),
+                )))?;
+            clones.insert(meta.id, clone);
+
+            Ok(Some(clone))
+        };
+
+        let mut archetypes = Vec::with_capacity(self.archetypes.len());
+        for archetype in &self.archetypes {
+            let columns = archetype.columns
+                .iter()
+                .map(|col| Ok(unsafe { col.clone_with(clone_fn(&col.meta, col.len)?) }))
+                .collect::<Result<Vec<_>, EcsError>>()?;
+            archetypes.push((columns, archetype.entities.clone()));
+        }
+
+        let mut sparse_sets = HashMap::new();
+        for (&id, set) in &self.sparse_sets {
+            sparse_sets.insert(id, unsafe { set.clone_with(clone_fn(set.meta(), set.len())?) });
+        }
+
+        Ok(WorldSnapshot {
+            world_id: self.id,
+            archetypes,
+            sparse_sets,
+            entities: self.entities.clone(),
+            clones,
+        })
+    }
+
+    /// Resets entities and components to the state of the snapshot, which
+    /// can be restored again afterwards. Entities of the snapshot are alive
+    /// again under their ids, entities spawned since then are dropped
+    /// without triggering hooks and their ids stay dead. Resources and the
+    /// change tick are kept.
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) -> Result<(), EcsError> {
+        if snapshot.world_id != self.id {
+            return Err(EcsError::ForeignSnapshot);
+        }
+
+        let clone = |col: &Column| unsafe { col.clone_with(snapshot.clones.get(&col.meta.id).copied()) };
+
+        // Archetypes are never removed, so the ones of the snapshot are the
+        // first ones of the world, with the same columns
+        for (index, archetype) in self.archetypes.iter_mut().enumerate() {
+            match snapshot.archetypes.get(index) {
+                Some((columns, entities)) => {
+                    archetype.columns = columns.iter().map(clone).collect();
+                    archetype.entities = entities.clone();
+                }
+                None => archetype.clear(),
+            }
+        }
+
+        for (id, set) in &mut self.sparse_sets {
+            match snapshot.sparse_sets.get(id) {
+                Some(saved) => *set = unsafe { saved.clone_with(snapshot.clones.get(id).copied()) },
+                None => set.clear(),
+            }
+        }
+
+        self.entities.restore(&snapshot.entities);
+
+        Ok(())
+    }
+
+    /// Moves the entities with all of their components into `dst`, e.g. to
+    /// merge a staging world which was loaded in the background. Returns
+    /// the entity in `dst` for each moved one.
+    ///
+    /// Moved entities are detached from parents and children which are not
+    /// moved with them, [`Parent`] and [`Children`] are always remapped.
+    /// Other entity references of components registered with
+    /// `with_map_entities` in the [`TypeRegistry`] of `dst` are remapped as
+    /// well, references to entities which were not moved become null. Add
+    /// hooks are triggered in `dst` after remapping, remove hooks are not
+    /// in `self`.
+    ///
+    /// Panics if the stable id of a moved component is used by another
+    /// component in `dst`, before anything is moved.
+    pub fn move_entities(
+        &mut self,
+        dst: &mut World,
+        entities: impl IntoIterator<Item = EntityId>,
+    ) -> HashMap<EntityId, EntityId> {
+        let mut moved = entities
+            .into_iter()
+            .filter(|&entity| self.is_alive(entity))
+            .collect::<Vec<_>>();
+        moved.sort();
+        moved.dedup();
+
+        let ids = moved.iter()
+            .flat_map(|&entity| self.entity_components(entity).into_iter().flatten())
+            .collect::<HashSet<_>>();
+        for id in ids {
+            if let Some(stable) = self.stable_ids.stable_id(id) {
+                let name = self.stable_ids.name(stable).unwrap_or_default().to_string();
+                if let Err(e) = dst.stable_ids.insert(stable, id, name) {
+                    panic!("{e}");
+                }
+            }
+        }
+
+        for &entity in &moved {
+            if self.parent(entity).is_some_and(|parent| moved.binary_search(&parent).is_err()) {
+                self.remove_parent(entity);
+            }
+            for child in self.children(entity).to_vec() {
+                if moved.binary_search(&child).is_err() {
+                    self.remove_parent(child);
+                }
+            }
+        }
+
+        // Reserve the targets first, so references are remapped before
+        // the add hooks of `dst` see the components
+        let map = moved.iter()
+            .map(|&entity| (entity, dst.spawn_erased(&[])))
+            .collect::<HashMap<_, _>>();
+        let mut remap = |id| map.get(&id).copied().unwrap_or_else(EntityId::null);
+
+        let registry = dst.resource::<TypeRegistry>();
+        for &entity in &moved {
+            if let Some(mut parent) = self.get_mut::<Parent>(entity) {
+                parent.map_entities(&mut remap);
+            }
+            if let Some(mut children) = self.get_mut::<Children>(entity) {
+                children.map_entities(&mut remap);
+            }
+
+            let Some(registry) = registry else {
+                continue;
+            };
+            let ids = self.entity_components(entity)
+                .map(|ids| ids.collect::<Vec<_>>())
+                .unwrap_or_default();
+            for id in ids {
+                if id == Parent::component_id() || id == Children::component_id() {
+                    continue;
+                }
+                let Some(registration) = registry.get_by_component(id) else {
+                    continue;
+                };
+                let Some(map_entities) = registration.map_entities else {
+                    continue;
+                };
+                if let Some(value) = registration.get_mut(self, entity) {
+                    map_entities(value, &mut remap);
+                }
+            }
+        }
+
+        for entity in moved {
+            let location = self.entities[entity];
+
+            let mut components = self.archetypes[location.archetype].columns
+                .iter()
+                .map(|col| erased(col.meta, StorageType::Table, col.get_ptr(location.row)))
+                .collect::<Vec<_>>();
+            for set in self.sparse_sets.values() {
+                if let Some(data) = set.get_ptr(entity) {
+                    components.push(erased(*set.meta(), StorageType::Sparse, data));
+                }
+            }
+
+            dst.insert_erased(map[&entity], &components)
+                .expect("Moved entity should be alive in the destination");
+
+            // The components are owned by `dst` now
+            for set in self.sparse_sets.values_mut() {
+                set.remove_forget(entity);
+            }
+            let archetype = &mut self.archetypes[location.archetype];
+            if let Some(swapped) = archetype.swap_remove_forget(location.row) {
+                self.entities[swapped].row = location.row;
+            }
+            archetype.debug_assert_columns();
+            self.entities.remove(entity);
+        }
+
+        map
+    }
+
+    /// Moves all entities of `other` into this world, see
+    /// [`World::move_entities`]
+    pub fn merge(&mut self, other: &mut World) -> HashMap<EntityId, EntityId> {
+        let entities = other.entities().collect::<Vec<_>>();
+        other.move_entities(self, entities)
+    }
+}
+
+/// Bundles with a component type twice would push two values into one
+/// column for a single entity
+fn check_duplicates(ids: &[ComponentId]) -> Result<(), EcsError> {
+    let mut sorted = ids.to_vec();
+    sorted.sort_unstable();
+
+    match sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        Some(pair) => Err(EcsError::DuplicateComponent(pair[0])),
+        None => Ok(()),
+    }
+}
+
+fn erased(meta: ComponentMeta, storage: StorageType, data: *const u8) -> ErasedComponent {
+    ErasedComponent {
+        id: meta.id,
+        data,
+        layout: meta.layout,
+        kind: meta.kind,
+        drop_fn: meta.drop_fn,
+        storage,
+        // Registered by `World::move_entities` up front
+        stable: None,
+    }
+}
+
+impl World {
+    fn find_or_create_archetype(
+        &mut self,
+        ids: &[ComponentId],
+        columns: impl FnOnce(&[Archetype]) -> Vec<Column>,
+    ) -> usize {
+        let mask = ArchetypeMask::from_ids(ids);
+
+        if let Some(&index) = self.archetype_index.get(&mask) {
+            return index;
+        }
+
+        let columns = columns(&self.archetypes);
+        debug_assert_eq!(columns.len(), ids.len(), "Archetype columns must match its components");
+
+        let index = self.archetypes.len();
+        self.archetypes.push(Archetype::new(mask.clone(), columns));
+        self.archetype_index.insert(mask, index);
+
+        index
+    }
+
+    fn archetype_with(
+        &mut self, 
+        from: usize, 
+        id: ComponentId, 
+        column: impl FnOnce() -> Column,
+    ) -> usize {
+        let source = &self.archetypes[from];
+        if source.has_components(&[id]) {
+            return from;
+        }
+        if let Some(&to) = source.edges.add.get(&id) {
+            return to;
+        }
+
+        let mut ids = source.component_ids().collect::<Vec<_>>();
+        ids.push(id);
+
+        let to = self.find_or_create_archetype(&ids, |archetypes| {
+            let mut columns = archetypes[from].columns
+                .iter()
+                .map(|col| Column::new(64, col.meta.id, col.meta.layout, col.meta.kind, col.meta.drop_fn))
+                .collect::<Vec<_>>();
+            columns.push(column());
+            columns
+        });
+
+        self.archetypes[from].edges.add.insert(id, to);
+        self.archetypes[to].edges.remove.insert(id, from);
+
+        to
+    }
+
+    fn archetype_without(&mut self, from: usize, id: ComponentId) -> usize {
+        if let Some(&to) = self.archetypes[from].edges.remove.get(&id) {
+            return to;
+        }
+
+        let ids = self.archetypes[from]
+            .component_ids()
+            .filter(|&other| other != id)
+            .collect::<Vec<_>>();
+
+        let to = self.find_or_create_archetype(&ids, |archetypes| {
+            archetypes[from].columns
+                .iter()
+                .filter(|col| col.meta.id != id)
+                .map(|col| Column::new(64, col.meta.id, col.meta.layout, col.meta.kind, col.meta.drop_fn))
+                .collect()
+        });
+
+        self.archetypes[from].edges.remove.insert(id, to);
+        self.archetypes[to].edges.add.insert(id, from);
+
+        to
+    }
+
+    /// Moves the entity row into another archetype, returning the new row
+    fn move_entity(&mut self, entity: EntityId, location: EntityLocation, target: usize) -> usize {
+        let (src, dst) = if location.archetype < target {
+            let (left, right) = self.archetypes.split_at_mut(target);
+            (&mut left[location.archetype], &mut right[0])
+        } else {
+            let (left, right) = self.archetypes.split_at_mut(location.archetype);
+            (&mut right[0], &mut left[target])
+        };
+
+        let (row, moved) = src.move_row(location.row, dst);
+        src.debug_assert_columns();
+        if let Some(moved) = moved {
+            self.entities[moved].row = location.row;
+        }
+        self.entities[entity] = EntityLocation {
+            archetype: target,
+            row,
+        };
+
+        row
     }
 }
 
@@ -133,47 +1325,84 @@ pub enum ComponentQuery<'a> {
 }
 
 impl World {
-    pub fn query_erased(&mut self, components: &[(ComponentId, Access)]) -> Vec<ErasedQueryResult<'_>> {
+    /// Collects the requested components of every entity having all of
+    /// them. Fails if a component is written and accessed again.
+    pub fn query_erased(&mut self, components: &[(ComponentId, Access)]) -> Result<Vec<ErasedQueryResult<'_>>, EcsError> {
+        validate_access_list("World::query_erased", components)?;
+
+        Ok(self.query_erased_filtered(components, &[], &[]))
+    }
+
+    /// Same as [`World::query_erased`], but only yields entities having every
+    /// component of `with` and none of `without`. Written components are
+    /// marked as changed. The caller must make sure no component is written
+    /// more than once.
+    pub(super) fn query_erased_filtered(
+        &mut self,
+        components: &[(ComponentId, Access)],
+        with: &[ComponentId],
+        without: &[ComponentId],
+    ) -> Vec<ErasedQueryResult<'_>> {
         let mut results = Vec::new();
-        let ids = components
+        let table_ids = components
             .iter()
             .map(|(id, _)| *id)
+            .chain(with.iter().copied())
+            .filter(|id| !self.sparse_sets.contains_key(id))
+            .collect::<Vec<_>>();
+        let sparse_with = with
+            .iter()
+            .filter_map(|id| self.sparse_sets.get(id))
+            .collect::<Vec<_>>();
+        let sparse_without = without
+            .iter()
+            .filter_map(|id| self.sparse_sets.get(id))
             .collect::<Vec<_>>();
 
+        let tick = self.change_tick();
         for archetype in &mut self.archetypes {
-            if archetype.has_components(&ids) {
+            let excluded = without.iter().any(|&id| archetype.mask.has(id));
+
+            if archetype.has_components(&table_ids) && !excluded {
                 let len = archetype.entities.len();
 
-                let column_indices: Vec<(usize, Access)> = components
-                    .iter()
-                    .map(|(id, access)| (
-                        archetype.columns.iter().position(|col| col.meta.id == *id).unwrap(),
-                        *access
-                    ))
-                    .collect();
-                let columns: Vec<(&Column, Access)> = column_indices
-                    .iter()
-                    .map(|&(idx, access)| (&archetype.columns[idx], access))
-                    .collect();
+                'rows: for i in 0..len {
+                    let entity = archetype.entities[i];
+                    if !sparse_with.iter().all(|set| set.contains(entity))
+                        || sparse_without.iter().any(|set| set.contains(entity))
+                    {
+                        continue;
+                    }
 
-                for i in 0..len {
                     let mut comps = Vec::with_capacity(components.len());
-                    for (col, access) in &columns {
+
+                    for &(id, access) in components {
+                        let (ptr, ticks, size) = match self.sparse_sets.get(&id) {
+                            Some(set) => match set.dense_index(entity) {
+                                Some(dense) => (set.get_ptr_at(dense), set.ticks_ptr_at(dense), set.meta().layout.size()),
+                                None => continue 'rows,
+                            },
+                            None => {
+                                let col = archetype
+                                    .get_column_with_component(id)
+                                    .expect("Matched archetype must have the column");
+                                (col.get_ptr(i), unsafe { col.ticks_ptr().add(i) }, col.meta.layout.size())
+                            }
+                        };
+
                         unsafe {
-                            let ptr = col.ptr.as_ptr().add(i * col.meta.layout.size());
-                            let slice = match *access {
-                                READ => ComponentQuery::Read(
-                                    std::slice::from_raw_parts(ptr, col.meta.layout.size()),
-                                ),
-                                WRITE => ComponentQuery::Write(
-                                    std::slice::from_raw_parts_mut(ptr, col.meta.layout.size()),
-                                ),
-                            };
-                            comps.push(slice);
+                            comps.push(match access {
+                                READ => ComponentQuery::Read(std::slice::from_raw_parts(ptr, size)),
+                                WRITE => {
+                                    (*ticks).changed = tick;
+                                    ComponentQuery::Write(std::slice::from_raw_parts_mut(ptr, size))
+                                }
+                            });
                         }
                     }
+
                     results.push(ErasedQueryResult {
-                        entity: archetype.entities[i],
+                        entity,
                         components: comps,
                     });
                 }
@@ -183,62 +1412,60 @@ impl World {
         results
     }
 
-    pub fn query<'w, Q: Query<'w>>(&'w mut self) -> Vec<Q::Result> {
-        Q::query_world(self)
+    /// Collects the items of every entity matching the query. Panics if the
+    /// query aliases a component mutably, see [`World::try_query`]. Systems
+    /// running every frame should keep a [`QueryState`] instead.
+    pub fn query<Q: Query>(&mut self) -> Vec<Q::Item<'_>> {
+        self.try_query::<Q>()
+            .unwrap_or_else(|e| panic!("{e}"))
     }
-}
 
-pub trait Query<'w> {
-    type Result: 'w;
+    pub fn try_query<Q: Query>(&mut self) -> Result<Vec<Q::Item<'_>>, EcsError> {
+        self.try_query_filtered::<Q, ()>()
+    }
 
-    fn query_world(world: &'w mut World) -> Vec<Self::Result>;
-}
+    /// Same as [`World::query`], but only yields entities passing the filter.
+    /// Tick-based filters report changes made since [`World::last_change_tick`].
+    pub fn query_filtered<Q: Query, F: QueryFilter>(&mut self) -> Vec<Q::Item<'_>> {
+        self.try_query_filtered::<Q, F>()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-impl<'w, A: Component + 'w> Query<'w> for &A {
-    type Result = &'w A;
+    pub fn try_query_filtered<Q: Query, F: QueryFilter>(&mut self) -> Result<Vec<Q::Item<'_>>, EcsError> {
+        let mut state = QueryState::<Q, F>::new(self)?;
 
-    fn query_world(world: &'w mut World) -> Vec<Self::Result> {
-        world
-            .query_erased(&[(A::component_id(), READ)])
-            .into_iter()
-            .map(|res| {
-                let ComponentQuery::Read(comp_a) = &res.components[0] else { unreachable!() };
-                unsafe { &*(comp_a.as_ptr() as *const A) }
-            })
-            .collect()
+        Ok(state.iter(self).collect())
     }
-}
 
-impl<'w, A: Component + 'w, B: Component + 'w> Query<'w> for (&A, &B) {
-    type Result = (&'w A, &'w B);
+    /// Creates a cached query, panicking if it aliases a component mutably
+    pub fn query_state<Q: Query>(&self) -> QueryState<Q> {
+        self.query_state_filtered()
+    }
 
-    fn query_world(world: &'w mut World) -> Vec<Self::Result> {
-        world
-            .query_erased(&[
-                (A::component_id(), READ),
-                (B::component_id(), READ),
-            ])
-            .into_iter()
-            .map(|res| {
-                let ComponentQuery::Read(comp_a) = &res.components[0] else { unreachable!() };
-                let ComponentQuery::Read(comp_b) = &res.components[1] else { unreachable!() };
-                (
-                    unsafe { &*(comp_a.as_ptr() as *const A) },
-                    unsafe { &*(comp_b.as_ptr() as *const B) }
-                )
-            })
-            .collect()
+    pub fn query_state_filtered<Q: Query, F: QueryFilter>(&self) -> QueryState<Q, F> {
+        QueryState::new(self)
+            .unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
-pub trait ComponentsBundle {
+/// Components spawned or inserted together, usually implemented with
+/// `#[derive(Bundle)]`.
+///
+/// # Safety
+/// `for_each` must pass every component of the bundle exactly once. The
+/// world moves them out bytewise and forgets the bundle, so they must be
+/// owned by it. They must also be fields of the bundle itself, of the same
+/// types at the same offsets for every value of the type, so that
+/// [`World::spawn_batch`] can copy them by offset.
+pub unsafe trait ComponentsBundle {
     fn for_each(&self, f: &mut dyn FnMut(&dyn Component));
 }
 
 macro_rules! impl_components_bundle_tuple {
     () => {};
     ($($name:ident),+) => {
-        impl<$($name: Component),+> ComponentsBundle for ($($name,)+) {
+        // SAFETY: every element of the tuple is passed once
+        unsafe impl<$($name: Component),+> ComponentsBundle for ($($name,)+) {
             fn for_each(&self, f: &mut dyn FnMut(&dyn Component)) {
                 #[allow(non_snake_case)]
                 let ($($name,)+) = self;
@@ -263,4 +1490,116 @@ impl_components_bundle_tuple! { A, B, C, D, E, F, G, H, I, J, K, L }
 impl_components_bundle_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M }
 impl_components_bundle_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N }
 impl_components_bundle_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N, O }
-impl_components_bundle_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P }
\ No newline at end of file
+impl_components_bundle_tuple! { A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P }
+
+#[cfg(test)]
+mod tests {
+    use slotmap::Key;
+
+    use super::*;
+    use crate::ecs::reflect::Reflect;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    struct Position(i32);
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    struct Name(String);
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[component(sparse)]
+    struct Tags(Vec<u8>);
+
+    #[test]
+    fn despawned_ids_are_not_reused() {
+        let mut world = World::new();
+        let first = world.spawn((Position(1),));
+        assert!(world.despawn(first));
+        assert!(!world.despawn(first));
+
+        // Same slot, next generation
+        let second = world.spawn((Position(2),));
+        assert_eq!(first.data().as_ffi() as u32, second.data().as_ffi() as u32);
+        assert_ne!(first, second);
+        assert!(!world.is_alive(first));
+        assert!(world.is_alive(second));
+        assert_eq!(world.query::<&Position>(), [&Position(2)]);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut world = World::new();
+        let mut registry = TypeRegistry::new();
+        registry.register::<Name>().with_clone().with_component();
+        registry.register::<Tags>().with_clone().with_component();
+        world.insert_resource(registry);
+
+        let a = world.spawn((Position(1), Name("a".into())));
+        let b = world.spawn((Position(2), Tags(vec![1])));
+        let snapshot = world.snapshot().unwrap();
+
+        world.get_mut::<Position>(a).unwrap().0 = 10;
+        world.get_mut::<Name>(a).unwrap().0 = "changed".into();
+        world.remove::<Tags>(b);
+        world.despawn(b);
+        world.spawn((Position(3), Name("c".into()), Tags(vec![3])));
+        world.restore(&snapshot).unwrap();
+
+        assert_eq!(world.entities().count(), 2);
+        assert_eq!(world.get::<Position>(a), Some(&Position(1)));
+        assert_eq!(world.get::<Name>(a), Some(&Name("a".into())));
+        assert_eq!(world.get::<Position>(b), Some(&Position(2)));
+        assert_eq!(world.get::<Tags>(b), Some(&Tags(vec![1])));
+        assert_eq!(world.query::<&Name>().len(), 1);
+        assert_eq!(world.query::<&Tags>().len(), 1);
+
+        // The snapshot is left intact by restoring it
+        world.despawn(a);
+        world.restore(&snapshot).unwrap();
+        assert_eq!(world.get::<Name>(a), Some(&Name("a".into())));
+    }
+
+    #[test]
+    fn restore_keeps_handles_of_later_entities_dead() {
+        let mut world = World::new();
+        let kept = world.spawn((Position(1),));
+        let snapshot = world.snapshot().unwrap();
+
+        let later = world.spawn((Position(2),));
+        world.restore(&snapshot).unwrap();
+
+        assert!(world.is_alive(kept));
+        assert!(!world.is_alive(later));
+        for i in 0..100 {
+            let spawned = world.spawn((Position(i),));
+            assert_ne!(spawned, later);
+            if i % 2 == 0 {
+                world.despawn(spawned);
+            }
+        }
+        assert!(!world.is_alive(later));
+        assert_eq!(world.get::<Position>(kept), Some(&Position(1)));
+    }
+
+    #[test]
+    fn move_entities_remaps_hierarchy_without_registry() {
+        let mut src = World::new();
+        src.spawn((Position(-1),));
+        src.spawn((Position(-2),));
+        let parent = src.spawn((Position(0),));
+        let child = src.spawn((Position(1),));
+        src.set_parent(child, parent).unwrap();
+
+        let mut dst = World::new();
+        dst.on_add::<Parent>(|world, entity| {
+            let parent = world.get::<Parent>(entity).unwrap().get();
+            assert!(world.is_alive(parent), "Parent must be remapped before hooks run");
+        });
+
+        let map = src.move_entities(&mut dst, [parent, child]);
+        assert!(!dst.contains_resource::<TypeRegistry>());
+        assert_eq!(src.entities().count(), 2);
+        assert_eq!(dst.parent(map[&child]), Some(map[&parent]));
+        assert_eq!(dst.children(map[&parent]), &[map[&child]]);
+        assert_eq!(dst.get::<Position>(map[&child]), Some(&Position(1)));
+    }
+}
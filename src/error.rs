@@ -1,7 +1,7 @@
 use glfw::InitError;
 use thiserror::Error;
 
-use crate::render::error::RenderError;
+use crate::{ecs::error::EcsError, render::error::RenderError};
 
 #[derive(Debug, Error)]
 pub enum GameError {
@@ -10,4 +10,7 @@ pub enum GameError {
 
     #[error("Render error: {0}")]
     Render(#[from] RenderError),
+
+    #[error("ECS error: {0}")]
+    Ecs(#[from] EcsError),
 }
\ No newline at end of file
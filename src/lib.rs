@@ -1,3 +1,6 @@
+// Lets derive macros refer to `::kvantuma` inside this crate too
+extern crate self as kvantuma;
+
 pub mod app;
 pub mod ecs;
 pub mod render;
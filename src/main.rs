@@ -3,24 +3,23 @@ use kvantuma::{
     app::{
         App, Game,
         window::{WindowDescriptor, WindowMode},
-    }, component, ecs::world::World, render::{
+    }, ecs::{component::Component, filter::Changed, world::World}, render::{
         Drawable, RenderDevice, RenderSurface, 
         buffer::BufferHandle, 
         error::RenderError, 
-        material::{TintedTextureMaterial, Vertex}, 
+        material::{Material, TintedTextureMaterial, Vertex}, 
         pass::DrawDescriptor, 
         registry::RenderRegistry,
         types::*,
     }
 };
 
+#[derive(Component)]
 pub struct Triangle {
     pub vertex_data: [Vertex; 3],
     pub vertex_buffer: Option<BufferHandle>,
 }
 
-component! { EXTERN: Triangle }
-
 impl Drawable for Triangle {
     fn update(
         &mut self, 
@@ -71,25 +70,34 @@ impl Default for Triangle {
     }
 }
 
-struct KvantumaGame {
-    registry: RenderRegistry,
-}
+struct KvantumaGame;
 
 impl Game for KvantumaGame {
     fn init(&mut self, world: &mut World, render_device: &mut RenderDevice) -> anyhow::Result<()> {
-        self.registry.register_material::<TintedTextureMaterial>(render_device);
-        
-        let mut triangle = Triangle::default();
-        triangle.update(render_device, &mut self.registry);
+        let registry = world
+            .resource_mut::<RenderRegistry>()
+            .expect("RenderRegistry is inserted by App");
+
+        registry.register_material::<TintedTextureMaterial>(render_device);
 
         let material = TintedTextureMaterial::new(
             "assets/textures/test.png", 
             Vec3::new(0.0, 1.0, 0.5), 
             render_device, 
-            &mut self.registry,
+            registry,
         )?;
 
-        world.spawn((triangle, material));
+        world.on_remove::<Triangle>(|world, entity| {
+            let buffer = world
+                .get::<Triangle>(entity)
+                .and_then(|triangle| triangle.vertex_buffer);
+
+            if let (Some(buffer), Some(registry)) = (buffer, world.resource_mut::<RenderRegistry>()) {
+                registry.remove_buffer(buffer);
+            }
+        });
+
+        world.spawn((Triangle::default(), material));
 
         Ok(())
     }
@@ -103,25 +111,35 @@ impl Game for KvantumaGame {
     }
 
     fn render(&mut self, world: &mut World, render_device: &mut RenderDevice) -> Result<(), RenderError> {
-        let canvas = render_device.canvas()?;
-        let canvases: &[&dyn RenderSurface] = &[&canvas];
-        let mut ctx = render_device.draw_ctx();
+        world.resource_scope(|world, registry: &mut RenderRegistry| {
+            for mut triangle in world.query_filtered::<&mut Triangle, Changed<Triangle>>() {
+                triangle.update(render_device, registry);
+            }
 
-        let (triangle, material) = world.query::<(&Triangle, &TintedTextureMaterial)>()[0];
+            for material in world.query_filtered::<&TintedTextureMaterial, Changed<TintedTextureMaterial>>() {
+                material.update(render_device, registry);
+            }
 
-        {
-            let mut render_pass = ctx.render_pass(canvases, render_device.depth_texture());
+            let canvas = render_device.canvas()?;
+            let canvases: &[&dyn RenderSurface] = &[&canvas];
+            let mut ctx = render_device.draw_ctx();
 
-            render_pass.draw(render_device, &self.registry, DrawDescriptor::<(), _> {
-                drawable: Some(triangle),
-                instance_data: None,
-                material,
-            });
-        }
+            let (triangle, material) = world.query::<(&Triangle, &TintedTextureMaterial)>()[0];
 
-        ctx.apply(canvas, render_device);
+            {
+                let mut render_pass = ctx.render_pass(canvases, render_device.depth_texture());
 
-        Ok(())
+                render_pass.draw(render_device, registry, DrawDescriptor::<(), _> {
+                    drawable: Some(triangle),
+                    instance_data: None,
+                    material,
+                });
+            }
+
+            ctx.apply(canvas, render_device);
+
+            Ok(())
+        }).expect("RenderRegistry is inserted by App")
     }
 }
 
@@ -135,9 +153,7 @@ fn main() -> anyhow::Result<()> {
             title: "KVΛNTUMA",
             mode: WindowMode::Windowed,
         }, 
-        KvantumaGame {
-            registry: RenderRegistry::new(),
-        },
+        KvantumaGame,
     )?.run();
 
     Ok(())
@@ -9,6 +9,7 @@ use crate::render::texture::{Texture, TextureDescriptor, TextureResourceDescript
 
 use super::{shader_resource::{ShaderResource, ShaderResourceLayout}, registry::RenderRegistry, texture::TextureHandle};
 use super::types::*;
+use super::vertex::VertexLayout;
 
 pub trait Material {
     fn shader() -> ShaderModuleDescriptor<'static>;
@@ -22,30 +23,19 @@ pub trait Material {
         render_device: &RenderDevice,
         registry: &RenderRegistry,
     ) -> ShaderResource;
+
+    /// Uploads uniform data of the material, meant to be called only when
+    /// the material component has changed
+    fn update(&self, _render_device: &RenderDevice, _registry: &mut RenderRegistry) {}
 }
 
-#[derive(Pod, Zeroable, Clone, Copy)]
+#[derive(Pod, Zeroable, Clone, Copy, VertexLayout)]
 #[repr(C)]
 pub struct Vertex {
     pub position: Vec3,
     pub texcoord: Vec2,
 }
 
-impl Vertex {
-    const ATTRIBS: &[wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
-        0 => Float32x3,
-        1 => Float32x2,
-    ];
-
-    pub fn vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: Self::ATTRIBS,
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct TintedTextureMaterial {
     pub albedo: TextureHandle,
@@ -117,4 +107,9 @@ impl Material for TintedTextureMaterial {
                 &TintedTextureMaterial::shader_resource_layout(render_device),
             )
     }
+
+    fn update(&self, render_device: &RenderDevice, registry: &mut RenderRegistry) {
+        self.tint_buffer
+            .and_then_mut(registry, |b| b.fill(render_device, 0, &[self.tint]));
+    }
 }
\ No newline at end of file
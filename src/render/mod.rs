@@ -15,6 +15,7 @@ pub mod pass;
 pub mod draw_context;
 pub mod shader_resource;
 pub mod mesh;
+pub mod vertex;
 
 pub mod types {
     pub use wgpu::{
@@ -32,6 +33,10 @@ pub mod types {
         SamplerBindingType,
         ShaderModuleDescriptor,
         VertexBufferLayout,
+        VertexAttribute,
+        VertexFormat,
+        VertexStepMode,
+        BufferAddress,
     };
 }
 
@@ -64,6 +64,11 @@ impl RenderRegistry {
         self.buffers.get_mut(handle)
     }
 
+    /// Frees the buffer, e.g. from a remove hook of the component owning it
+    pub fn remove_buffer(&mut self, handle: BufferHandle) -> Option<BufferStorage> {
+        self.buffers.remove(handle)
+    }
+
     pub fn new_texture(
         &mut self,
         render_device: &RenderDevice,
@@ -97,4 +102,8 @@ impl RenderRegistry {
     pub fn get_texture_mut(&mut self, handle: TextureHandle) -> Option<&mut Texture> {
         self.textures.get_mut(handle)
     }
+
+    pub fn remove_texture(&mut self, handle: TextureHandle) -> Option<Texture> {
+        self.textures.remove(handle)
+    }
 }
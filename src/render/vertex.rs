@@ -0,0 +1,50 @@
+use glam::{IVec2, IVec3, IVec4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
+
+use super::types::{VertexBufferLayout, VertexFormat};
+
+pub use kvantuma_derive::VertexLayout;
+
+/// Layout of a vertex or instance buffer element, usually derived with
+/// `#[derive(VertexLayout)]`
+pub trait VertexLayout {
+    fn vertex_buffer_layout() -> VertexBufferLayout<'static>;
+}
+
+/// Shader format of a vertex attribute type
+pub trait VertexAttributeFormat {
+    const FORMAT: VertexFormat;
+}
+
+macro_rules! impl_vertex_attribute_format {
+    ($($ty:ty => $format:ident),+ $(,)?) => {
+        $(
+            impl VertexAttributeFormat for $ty {
+                const FORMAT: VertexFormat = VertexFormat::$format;
+            }
+        )+
+    };
+}
+
+impl_vertex_attribute_format! {
+    f32 => Float32,
+    [f32; 2] => Float32x2,
+    [f32; 3] => Float32x3,
+    [f32; 4] => Float32x4,
+    Vec2 => Float32x2,
+    Vec3 => Float32x3,
+    Vec4 => Float32x4,
+    u32 => Uint32,
+    [u32; 2] => Uint32x2,
+    [u32; 3] => Uint32x3,
+    [u32; 4] => Uint32x4,
+    UVec2 => Uint32x2,
+    UVec3 => Uint32x3,
+    UVec4 => Uint32x4,
+    i32 => Sint32,
+    [i32; 2] => Sint32x2,
+    [i32; 3] => Sint32x3,
+    [i32; 4] => Sint32x4,
+    IVec2 => Sint32x2,
+    IVec3 => Sint32x3,
+    IVec4 => Sint32x4,
+}